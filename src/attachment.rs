@@ -0,0 +1,177 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::{CredentialName, Error, ErrorKind, Result};
+use crate::journal::Journal;
+use crate::pgp::{decrypt_data, encrypt_data};
+use crate::store::Store;
+
+pub(crate) const ATTACHMENTS_DIR: &str = ".attachments";
+
+pub(crate) fn attachments_path(name: &str) -> String {
+    format!("{}/{}", ATTACHMENTS_DIR, name)
+}
+
+pub(crate) fn attachment_path(name: &CredentialName, attachment: &CredentialName) -> String {
+    format!("{}/{}", attachments_path(name.as_str()), attachment)
+}
+
+fn ensure_credential(repo_path: &Path, name: &CredentialName) -> Result<()> {
+    if name.path_in(repo_path).is_file() {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::NotFound,
+            format!("no credential found for {:?}", name.as_str()),
+        ))
+    }
+}
+
+impl Store {
+    pub fn add_attachment(&self, name: &str, attachment: &str, data: &[u8]) -> Result<()> {
+        let name = CredentialName::new(name)?;
+        let attachment = CredentialName::segment(attachment)?;
+        let repo_path = self.repo_path();
+        let repository = self.open()?;
+        let _lock = self.lock(&repository)?;
+        ensure_credential(repo_path, &name)?;
+
+        let relative_path = attachment_path(&name, &attachment);
+        let pub_key = self.rsa_pub_key()?;
+        let mut journal = Journal::new(repo_path);
+
+        if repo_path.join(&relative_path).exists() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                "An attachment already exists with this name",
+            ));
+        }
+
+        journal.write(&relative_path, &encrypt_data(data, pub_key)?)?;
+
+        journal.commit(
+            &repository,
+            &self.signature()?,
+            Some(vec![relative_path.as_str()]),
+            None,
+            &format!(
+                "add attachment {:?} to {:?}",
+                attachment.as_str(),
+                name.as_str()
+            ),
+        )
+    }
+
+    pub fn get_attachment(&self, name: &str, attachment: &str, password: &str) -> Result<Vec<u8>> {
+        let name = CredentialName::new(name)?;
+        let attachment = CredentialName::segment(attachment)?;
+        let private_key = self.private_key()?;
+        let file_path = self.repo_path().join(attachment_path(&name, &attachment));
+
+        let buffer = fs::read(&file_path).map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "no attachment {:?} found for {:?}",
+                    attachment.as_str(),
+                    name.as_str()
+                ),
+            )
+            .with_source(err),
+            io::ErrorKind::PermissionDenied => Error::new(
+                ErrorKind::PermissionDenied,
+                "You dont have permission to read this attachment",
+            )
+            .with_source(err),
+            _ => Error::new(
+                ErrorKind::Io,
+                format!("failed to read attachment {:?}. {}", file_path, err),
+            )
+            .with_source(err),
+        })?;
+
+        let mut data = decrypt_data(vec![buffer], password, private_key)?;
+
+        Ok(data.remove(0))
+    }
+
+    pub fn list_attachments(&self, name: &str) -> Result<Vec<String>> {
+        let name = CredentialName::new(name)?;
+        let repo_path = self.repo_path();
+        self.open()?;
+        ensure_credential(repo_path, &name)?;
+
+        let entries = match fs::read_dir(repo_path.join(attachments_path(name.as_str()))) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => match err.kind() {
+                io::ErrorKind::PermissionDenied => {
+                    return Err(Error::new(
+                        ErrorKind::PermissionDenied,
+                        "You dont have permission to read the repository",
+                    )
+                    .with_source(err))
+                }
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::Io,
+                        format!("failed to list attachments. {}", err),
+                    )
+                    .with_source(err))
+                }
+            },
+        };
+
+        let mut attachments: Vec<String> = entries
+            .flatten()
+            .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_file()))
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect();
+
+        attachments.sort();
+
+        Ok(attachments)
+    }
+
+    pub fn remove_attachment(&self, name: &str, attachment: &str) -> Result<()> {
+        let name = CredentialName::new(name)?;
+        let attachment = CredentialName::segment(attachment)?;
+        let repo_path = self.repo_path();
+        let relative_path = attachment_path(&name, &attachment);
+
+        let repository = self.open()?;
+        let _lock = self.lock(&repository)?;
+        let mut journal = Journal::new(repo_path);
+
+        journal.remove(&relative_path)?;
+
+        journal.commit(
+            &repository,
+            &self.signature()?,
+            None,
+            Some(vec![relative_path.as_str()]),
+            &format!(
+                "remove attachment {:?} from {:?}",
+                attachment.as_str(),
+                name.as_str()
+            ),
+        )
+    }
+}
+
+pub fn add_attachment(name: &str, attachment: &str, data: &[u8]) -> Result<()> {
+    Store::default().add_attachment(name, attachment, data)
+}
+
+pub fn get_attachment(name: &str, attachment: &str, password: &str) -> Result<Vec<u8>> {
+    Store::default().get_attachment(name, attachment, password)
+}
+
+pub fn list_attachments(name: &str) -> Result<Vec<String>> {
+    Store::default().list_attachments(name)
+}
+
+pub fn remove_attachment(name: &str, attachment: &str) -> Result<()> {
+    Store::default().remove_attachment(name, attachment)
+}
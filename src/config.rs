@@ -1,14 +1,29 @@
-use std::path::PathBuf;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::OnceLock;
+use std::time::Duration;
 
 #[cfg(feature = "dirs")]
 use dirs::{config_dir, home_dir};
+use git2::{Reference, Remote, Signature};
+use toml::{Table, Value};
 
 use super::{Error, ErrorKind, Result};
+use crate::pgp::{recover_private_key, recover_rsa_pub_key};
+use crate::store::Store;
 
 static CONFIG_DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
 static HOME_DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
 
+pub const STORE_DIR_ENV: &str = "RSPASS_STORE_DIR";
+pub const CONFIG_DIR_ENV: &str = "RSPASS_CONFIG_DIR";
+pub const KEY_ENV: &str = "RSPASS_KEY";
+pub const PASS_STORE_DIR_ENV: &str = "PASSWORD_STORE_DIR";
+pub const PASS_STORE_KEY_ENV: &str = "PASSWORD_STORE_KEY";
+
+pub(crate) const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub fn get_home_dir() -> Option<PathBuf> {
     #[cfg(feature = "dirs")]
     return HOME_DIR.get_or_init(|| home_dir()).clone();
@@ -25,6 +40,33 @@ pub fn get_config_dir() -> Option<PathBuf> {
     CONFIG_DIR.get().cloned().flatten()
 }
 
+fn env_path(name: &str) -> Option<PathBuf> {
+    std::env::var_os(name)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+        .map(|path| std::path::absolute(&path).unwrap_or(path))
+}
+
+fn pass_env_path(name: &str, pass_compatibility: bool) -> Option<PathBuf> {
+    pass_compatibility.then(|| env_path(name)).flatten()
+}
+
+pub(crate) fn env_store_dir(pass_compatibility: bool) -> Option<PathBuf> {
+    env_path(STORE_DIR_ENV).or_else(|| pass_env_path(PASS_STORE_DIR_ENV, pass_compatibility))
+}
+
+pub(crate) fn env_config_dir() -> Option<PathBuf> {
+    env_path(CONFIG_DIR_ENV)
+}
+
+// `pass` stores a GPG key id in PASSWORD_STORE_KEY, which rspass cannot use, so
+// the variable is only honored when it points to a key file.
+pub(crate) fn env_private_key(pass_compatibility: bool) -> Option<PathBuf> {
+    env_path(KEY_ENV).or_else(|| {
+        pass_env_path(PASS_STORE_KEY_ENV, pass_compatibility).filter(|path| path.is_file())
+    })
+}
+
 pub fn set_home_dir(path: PathBuf) -> Result<()> {
     HOME_DIR
         .set(Some(path))
@@ -40,3 +82,435 @@ pub fn set_config_dir(path: PathBuf) -> Result<()> {
 
     Ok(())
 }
+
+pub(crate) const CONFIG_FILE: &str = "config.toml";
+pub(crate) const STORE_CONFIG_FILE: &str = ".rspass.toml";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    Git,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeysConfig {
+    pub public_key: PathBuf,
+    pub private_key: PathBuf,
+    pub rsa_public_key: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitConfig {
+    pub branch: String,
+    pub remote: String,
+    pub author_name: String,
+    pub author_email: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeneratorConfig {
+    pub length: usize,
+    pub symbols: bool,
+}
+
+/// Settings of a store, read from `config.toml` in the config directory and then from
+/// `.rspass.toml` at the root of the repository.
+///
+/// The repository file is shared through the remotes, so it may only override the `git` and
+/// `generator` sections. Key locations and the store section are always taken from the local
+/// file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StoreConfig {
+    pub backend: Backend,
+    /// Repository of a profile, relative to its profile directory. Ignored outside profiles.
+    pub path: Option<PathBuf>,
+    pub keys: KeysConfig,
+    pub git: GitConfig,
+    pub generator: GeneratorConfig,
+}
+
+impl Default for KeysConfig {
+    fn default() -> Self {
+        KeysConfig {
+            public_key: PathBuf::from("rspass.pub"),
+            private_key: PathBuf::from("rspass.key"),
+            rsa_public_key: PathBuf::from("rspass.pem"),
+        }
+    }
+}
+
+impl Default for GitConfig {
+    fn default() -> Self {
+        GitConfig {
+            branch: "master".to_owned(),
+            remote: "origin".to_owned(),
+            author_name: "rspass".to_owned(),
+            author_email: "rspass@rspass".to_owned(),
+        }
+    }
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        GeneratorConfig {
+            length: 16,
+            symbols: true,
+        }
+    }
+}
+
+fn bad_config(key: &str, reason: impl Into<String>) -> Error {
+    Error::new(
+        ErrorKind::BadConfig,
+        format!("invalid config key {:?}: {}", key, reason.into()),
+    )
+}
+
+fn string_value(key: &str, value: Value) -> Result<String> {
+    match value {
+        Value::String(value) if !value.trim().is_empty() => Ok(value),
+        Value::String(_) => Err(bad_config(key, "value must not be empty")),
+        _ => Err(bad_config(key, "expected a string")),
+    }
+}
+
+fn ref_name_value(key: &str, value: Value) -> Result<String> {
+    let branch = string_value(key, value)?;
+
+    if Reference::is_valid_name(&format!("refs/heads/{}", branch)) {
+        Ok(branch)
+    } else {
+        Err(bad_config(
+            key,
+            format!("{:?} is not a valid branch name", branch),
+        ))
+    }
+}
+
+fn remote_name_value(key: &str, value: Value) -> Result<String> {
+    let remote = string_value(key, value)?;
+
+    if Remote::is_valid_name(&remote) {
+        Ok(remote)
+    } else {
+        Err(bad_config(
+            key,
+            format!("{:?} is not a valid remote name", remote),
+        ))
+    }
+}
+
+fn length_value(key: &str, value: Value) -> Result<usize> {
+    match value {
+        Value::Integer(length) if (4..=1024).contains(&length) => Ok(length as usize),
+        Value::Integer(_) => Err(bad_config(key, "length must be between 4 and 1024")),
+        _ => Err(bad_config(key, "expected an integer")),
+    }
+}
+
+fn bool_value(key: &str, value: Value) -> Result<bool> {
+    match value {
+        Value::Boolean(value) => Ok(value),
+        _ => Err(bad_config(key, "expected a boolean")),
+    }
+}
+
+fn backend_value(key: &str, value: Value) -> Result<Backend> {
+    match string_value(key, value)?.as_str() {
+        "git" => Ok(Backend::Git),
+        other => Err(bad_config(key, format!("unsupported backend {:?}", other))),
+    }
+}
+
+impl StoreConfig {
+    /// Parses a config file on top of the default settings.
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut config = StoreConfig::default();
+        config.apply(contents, true)?;
+
+        Ok(config)
+    }
+
+    fn apply(&mut self, contents: &str, local: bool) -> Result<()> {
+        let table: Table = contents.parse().map_err(|err: toml::de::Error| {
+            Error::new(
+                ErrorKind::BadConfig,
+                format!("invalid config file. {}", err.message()),
+            )
+            .with_source(err)
+        })?;
+
+        for (section, entries) in table {
+            let Value::Table(entries) = entries else {
+                return Err(bad_config(&section, "expected a table"));
+            };
+
+            for (name, value) in entries {
+                let key = format!("{}.{}", section, name);
+
+                match (section.as_str(), name.as_str()) {
+                    ("store", "backend" | "path")
+                    | ("keys", "public_key" | "private_key" | "rsa_public_key")
+                        if !local =>
+                    {
+                        return Err(bad_config(&key, "can only be set in the local config"));
+                    }
+                    ("store", "backend") => self.backend = backend_value(&key, value)?,
+                    ("store", "path") => self.path = Some(string_value(&key, value)?.into()),
+                    ("keys", "public_key") => {
+                        self.keys.public_key = string_value(&key, value)?.into()
+                    }
+                    ("keys", "private_key") => {
+                        self.keys.private_key = string_value(&key, value)?.into()
+                    }
+                    ("keys", "rsa_public_key") => {
+                        self.keys.rsa_public_key = string_value(&key, value)?.into()
+                    }
+                    ("git", "branch") => self.git.branch = ref_name_value(&key, value)?,
+                    ("git", "remote") => self.git.remote = remote_name_value(&key, value)?,
+                    ("git", "author_name") => self.git.author_name = string_value(&key, value)?,
+                    ("git", "author_email") => self.git.author_email = string_value(&key, value)?,
+                    ("generator", "length") => self.generator.length = length_value(&key, value)?,
+                    ("generator", "symbols") => self.generator.symbols = bool_value(&key, value)?,
+                    _ => return Err(bad_config(&key, "unknown key")),
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub(crate) fn read_config_file(path: &Path) -> Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => match err.kind() {
+            io::ErrorKind::PermissionDenied => Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("You dont have permission to read {:?}", path),
+            )
+            .with_source(err)),
+            io::ErrorKind::InvalidData => Err(Error::new(
+                ErrorKind::BadConfig,
+                format!("{:?} is not valid UTF-8", path),
+            )
+            .with_source(err)),
+            _ => Err(
+                Error::new(ErrorKind::Io, format!("failed to read {:?}. {}", path, err))
+                    .with_source(err),
+            ),
+        },
+    }
+}
+
+impl Store {
+    /// Loads the settings of this store, falling back to the defaults for anything unset.
+    pub fn config(&self) -> Result<StoreConfig> {
+        let mut config = StoreConfig::default();
+
+        if let Ok(config_path) = self.config_path() {
+            if let Some(contents) = read_config_file(&config_path.join(CONFIG_FILE))? {
+                config.apply(&contents, true)?;
+            }
+        }
+
+        if let Some(contents) = read_config_file(&self.repo_path().join(STORE_CONFIG_FILE))? {
+            config.apply(&contents, false)?;
+        }
+
+        if let Some(private_key) = self.private_key_path() {
+            config.keys.private_key = private_key.to_path_buf();
+        }
+
+        Ok(config)
+    }
+
+    fn key_file(&self, path: &Path) -> Result<PathBuf> {
+        if path.is_absolute() {
+            Ok(path.to_path_buf())
+        } else {
+            Ok(self.keys_path()?.join(path))
+        }
+    }
+
+    pub(crate) fn key_files(&self) -> Result<(PathBuf, PathBuf, PathBuf)> {
+        let KeysConfig {
+            public_key,
+            private_key,
+            rsa_public_key,
+        } = self.config()?.keys;
+
+        Ok((
+            self.key_file(&public_key)?,
+            self.key_file(&private_key)?,
+            self.key_file(&rsa_public_key)?,
+        ))
+    }
+
+    pub(crate) fn private_key(&self) -> Result<String> {
+        recover_private_key(&self.key_file(&self.config()?.keys.private_key)?)
+    }
+
+    pub(crate) fn rsa_pub_key(&self) -> Result<String> {
+        recover_rsa_pub_key(&self.key_file(&self.config()?.keys.rsa_public_key)?)
+    }
+
+    pub(crate) fn signature(&self) -> Result<Signature<'static>> {
+        let GitConfig {
+            author_name,
+            author_email,
+            ..
+        } = self.config()?.git;
+
+        Signature::now(&author_name, &author_email)
+            .map_err(|err| bad_config("git.author_name", err.message()).with_source(err))
+    }
+}
+
+pub fn get_store_config() -> Result<StoreConfig> {
+    Store::default().config()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_bad_key(result: Result<StoreConfig>, key: &str) {
+        let err = result.unwrap_err();
+
+        assert_eq!(err.kind, ErrorKind::BadConfig);
+        assert!(
+            err.message.contains(&format!("{:?}", key)),
+            "{:?} does not name {:?}",
+            err.message,
+            key
+        );
+    }
+
+    fn apply_shared(contents: &str) -> Result<StoreConfig> {
+        let mut config = StoreConfig::default();
+        config.apply(contents, false)?;
+
+        Ok(config)
+    }
+
+    #[test]
+    fn parses_every_section() {
+        let config = StoreConfig::parse(
+            r#"
+            [store]
+            backend = "git"
+            path = "work"
+
+            [keys]
+            public_key = "/keys/work.pub"
+            private_key = "work.key"
+            rsa_public_key = "work.pem"
+
+            [git]
+            branch = "main"
+            remote = "backup"
+            author_name = "Ada"
+            author_email = "ada@example.com"
+
+            [generator]
+            length = 32
+            symbols = false
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.backend, Backend::Git);
+        assert_eq!(config.path, Some(PathBuf::from("work")));
+        assert_eq!(config.keys.public_key, PathBuf::from("/keys/work.pub"));
+        assert_eq!(config.keys.private_key, PathBuf::from("work.key"));
+        assert_eq!(config.keys.rsa_public_key, PathBuf::from("work.pem"));
+        assert_eq!(config.git.branch, "main");
+        assert_eq!(config.git.remote, "backup");
+        assert_eq!(config.git.author_name, "Ada");
+        assert_eq!(config.git.author_email, "ada@example.com");
+        assert_eq!(config.generator.length, 32);
+        assert!(!config.generator.symbols);
+    }
+
+    #[test]
+    fn keeps_defaults_for_unset_keys() {
+        assert_eq!(StoreConfig::parse("").unwrap(), StoreConfig::default());
+
+        let config = StoreConfig::parse("[git]\nbranch = \"main\"").unwrap();
+
+        assert_eq!(config.git.branch, "main");
+        assert_eq!(config.git.remote, GitConfig::default().remote);
+        assert_eq!(config.keys, KeysConfig::default());
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert_bad_key(
+            StoreConfig::parse("[git]\nbranches = \"main\""),
+            "git.branches",
+        );
+        assert_bad_key(StoreConfig::parse("[sync]\ninterval = 5"), "sync.interval");
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        assert_bad_key(
+            StoreConfig::parse("[store]\nbackend = \"svn\""),
+            "store.backend",
+        );
+        assert_bad_key(
+            StoreConfig::parse("[keys]\nprivate_key = \" \""),
+            "keys.private_key",
+        );
+        assert_bad_key(StoreConfig::parse("[git]\nbranch = \"a..b\""), "git.branch");
+        assert_bad_key(StoreConfig::parse("[git]\nremote = \"a b\""), "git.remote");
+        assert_bad_key(
+            StoreConfig::parse("[git]\nauthor_name = 7"),
+            "git.author_name",
+        );
+        assert_bad_key(
+            StoreConfig::parse("[generator]\nlength = 3"),
+            "generator.length",
+        );
+        assert_bad_key(
+            StoreConfig::parse("[generator]\nlength = \"8\""),
+            "generator.length",
+        );
+        assert_bad_key(
+            StoreConfig::parse("[generator]\nsymbols = 1"),
+            "generator.symbols",
+        );
+        assert_bad_key(StoreConfig::parse("git = \"main\""), "git");
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let err = StoreConfig::parse("[git\nbranch = ").unwrap_err();
+
+        assert_eq!(err.kind, ErrorKind::BadConfig);
+    }
+
+    #[test]
+    fn reserves_keys_and_store_settings_for_the_local_config() {
+        for (contents, key) in [
+            ("[store]\nbackend = \"git\"", "store.backend"),
+            ("[store]\npath = \"work\"", "store.path"),
+            ("[keys]\npublic_key = \"a.pub\"", "keys.public_key"),
+            ("[keys]\nprivate_key = \"a.key\"", "keys.private_key"),
+            ("[keys]\nrsa_public_key = \"a.pem\"", "keys.rsa_public_key"),
+        ] {
+            assert!(StoreConfig::parse(contents).is_ok());
+            assert_bad_key(apply_shared(contents), key);
+        }
+    }
+
+    #[test]
+    fn lets_the_shared_config_override_git_and_generator() {
+        let config = apply_shared("[git]\nbranch = \"main\"\n[generator]\nlength = 20").unwrap();
+
+        assert_eq!(config.git.branch, "main");
+        assert_eq!(config.generator.length, 20);
+    }
+}
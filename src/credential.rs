@@ -0,0 +1,335 @@
+/// First line of every credential written by this version. Credentials without it predate
+/// escaping and are read verbatim.
+const FORMAT_MARKER: &str = "rspass-credential/1";
+
+#[derive(Debug, Clone, Default)]
+pub struct Credential {
+    pub password: String,
+    pub fields: Vec<(String, String)>,
+    pub notes: String,
+    /// Note lines found between fields, with the number of fields before each of them. They
+    /// are the first lines of `notes` and are written back in place while those are unchanged.
+    inline_notes: Vec<(usize, String)>,
+}
+
+impl PartialEq for Credential {
+    fn eq(&self, other: &Self) -> bool {
+        self.password == other.password && self.fields == other.fields && self.notes == other.notes
+    }
+}
+
+impl Eq for Credential {}
+
+pub(crate) fn escape(value: &str, escape_separator: bool) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    value.chars().for_each(|c| match c {
+        '\\' => escaped.push_str("\\\\"),
+        '\n' => escaped.push_str("\\n"),
+        '\r' => escaped.push_str("\\r"),
+        '\t' => escaped.push_str("\\t"),
+        '=' if escape_separator => escaped.push_str("\\="),
+        _ => escaped.push(c),
+    });
+
+    escaped
+}
+
+pub(crate) fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('t') => unescaped.push('\t'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped
+}
+
+pub(crate) fn split_field(line: &str) -> Option<(String, String)> {
+    let mut escaped = false;
+
+    for (position, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '=' => {
+                return Some((unescape(&line[..position]), unescape(&line[position + 1..])));
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn split_legacy_field(line: &str) -> Option<(String, String)> {
+    line.split_once('=')
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+}
+
+impl Credential {
+    pub fn new(password: impl Into<String>) -> Self {
+        Credential {
+            password: password.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_field(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.set(key, value);
+        self
+    }
+
+    pub fn with_notes(mut self, notes: impl Into<String>) -> Self {
+        self.notes = notes.into();
+        self
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn set(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        let key = key.into();
+        let value = value.into();
+
+        match self.fields.iter_mut().find(|(field, _)| *field == key) {
+            Some((_, current)) => *current = value,
+            None => self.fields.push((key, value)),
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let position = self.fields.iter().position(|(field, _)| field == key)?;
+
+        Some(self.fields.remove(position).1)
+    }
+
+    /// Parses a decrypted credential. Data without the format marker is read verbatim, so
+    /// backslashes in credentials written before escaping was introduced are kept as is.
+    pub fn parse(data: &str) -> Self {
+        let (data, escaped) = match data.strip_prefix(FORMAT_MARKER) {
+            Some("") => ("", true),
+            Some(rest) if rest.starts_with('\n') => (&rest[1..], true),
+            _ => (data, false),
+        };
+        let split = if escaped {
+            split_field
+        } else {
+            split_legacy_field
+        };
+        let (password, mut rest) = data.split_once('\n').unwrap_or((data, ""));
+        let mut credential = Credential::new(if escaped {
+            unescape(password)
+        } else {
+            password.to_owned()
+        });
+        let mut notes = Vec::new();
+
+        while !rest.is_empty() {
+            let (line, remaining) = rest.split_once('\n').unwrap_or((rest, ""));
+
+            if line.is_empty() {
+                notes.push(remaining);
+                break;
+            }
+
+            match split(line) {
+                Some((key, value)) => credential.fields.push((key, value)),
+                None => {
+                    credential
+                        .inline_notes
+                        .push((credential.fields.len(), line.to_owned()));
+                    notes.push(line);
+                }
+            }
+
+            rest = remaining;
+        }
+
+        credential.notes = notes.join("\n");
+        credential
+    }
+
+    /// Returns the note lines to write between fields and the notes left for the end, which
+    /// is everything once the lines that sat between fields have been edited.
+    fn split_notes(&self) -> (&[(usize, String)], Option<&str>) {
+        let notes = Some(self.notes.as_str()).filter(|notes| !notes.is_empty());
+        let mut rest = self.notes.as_str();
+
+        if self.inline_notes.is_empty() {
+            return (&[], notes);
+        }
+
+        for (index, (_, line)) in self.inline_notes.iter().enumerate() {
+            let remaining = if index == 0 {
+                Some(rest)
+            } else {
+                rest.strip_prefix('\n')
+            };
+
+            match remaining.and_then(|remaining| remaining.strip_prefix(line.as_str())) {
+                Some(remaining) if remaining.is_empty() || remaining.starts_with('\n') => {
+                    rest = remaining
+                }
+                _ => return (&[], notes),
+            }
+        }
+
+        (&self.inline_notes, rest.strip_prefix('\n'))
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut data = format!("{}\n{}", FORMAT_MARKER, escape(&self.password, false));
+        let (inline_notes, notes) = self.split_notes();
+        let mut inline_notes = inline_notes.iter().peekable();
+
+        for (position, (key, value)) in self.fields.iter().enumerate() {
+            while let Some((_, line)) = inline_notes.next_if(|(before, _)| *before <= position) {
+                data.push('\n');
+                data.push_str(line);
+            }
+
+            data.push_str(&format!("\n{}={}", escape(key, true), escape(value, false)));
+        }
+
+        inline_notes.for_each(|(_, line)| {
+            data.push('\n');
+            data.push_str(line);
+        });
+
+        if let Some(notes) = notes {
+            data.push_str("\n\n");
+            data.push_str(notes);
+        }
+
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(credential: &Credential) {
+        assert_eq!(&Credential::parse(&credential.serialize()), credential);
+    }
+
+    #[test]
+    fn round_trips_separators_in_password_and_fields() {
+        round_trip(
+            &Credential::new("pa=ss")
+                .with_field("user=name", "a=b")
+                .with_field("url", "https://example.com/?a=1&b=2"),
+        );
+    }
+
+    #[test]
+    fn round_trips_backslashes_and_newlines() {
+        round_trip(
+            &Credential::new("pa\\ss\nword\\n")
+                .with_field("key\\", "line one\nline two\\")
+                .with_field("tab", "a\tb\r\n"),
+        );
+    }
+
+    #[test]
+    fn round_trips_notes() {
+        round_trip(
+            &Credential::new("secret")
+                .with_field("user", "me")
+                .with_notes("first line\nkey=value in notes\n\nafter a blank line"),
+        );
+        round_trip(&Credential::new("").with_notes("only notes"));
+        round_trip(&Credential::new(""));
+    }
+
+    #[test]
+    fn keeps_field_order() {
+        let credential = Credential::new("secret")
+            .with_field("zeta", "1")
+            .with_field("alpha", "2")
+            .with_field("mu", "3");
+        let keys: Vec<_> = Credential::parse(&credential.serialize())
+            .fields
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+
+        assert_eq!(keys, ["zeta", "alpha", "mu"]);
+    }
+
+    #[test]
+    fn reads_legacy_credentials_verbatim() {
+        let credential = Credential::parse("pa\\ss\nuser=a\\nb\nurl=x=y\nfree text\n\nnotes\\t");
+
+        assert_eq!(credential.password, "pa\\ss");
+        assert_eq!(
+            credential.fields,
+            [
+                ("user".to_owned(), "a\\nb".to_owned()),
+                ("url".to_owned(), "x=y".to_owned()),
+            ]
+        );
+        assert_eq!(credential.notes, "free text\nnotes\\t");
+    }
+
+    #[test]
+    fn legacy_password_with_separator_is_not_a_field() {
+        let credential = Credential::parse("a=b\nuser=me");
+
+        assert_eq!(credential.password, "a=b");
+        assert_eq!(credential.get("user"), Some("me"));
+        assert_eq!(credential.fields.len(), 1);
+    }
+
+    #[test]
+    fn keeps_note_lines_between_fields_in_place() {
+        let legacy = "secret\nuser=me\nfirst note\nsecond note\nurl=x\nlast note\n\ntrailing";
+        let credential = Credential::parse(legacy);
+
+        assert_eq!(
+            credential.notes,
+            "first note\nsecond note\nlast note\ntrailing"
+        );
+        assert_eq!(
+            credential.serialize(),
+            format!("{}\n{}", FORMAT_MARKER, legacy)
+        );
+        round_trip(&credential);
+    }
+
+    #[test]
+    fn moves_edited_note_lines_after_the_fields() {
+        let mut credential = Credential::parse("secret\nfirst note\nuser=me");
+        credential.notes = "edited".to_owned();
+
+        assert_eq!(
+            credential.serialize(),
+            format!("{}\nsecret\nuser=me\n\nedited", FORMAT_MARKER)
+        );
+    }
+
+    #[test]
+    fn writes_the_format_marker() {
+        let data = Credential::new("pa\\ss").serialize();
+
+        assert!(data.starts_with(FORMAT_MARKER));
+        assert_eq!(Credential::parse(&data).password, "pa\\ss");
+    }
+}
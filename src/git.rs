@@ -1,34 +1,54 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::config::get_home_dir;
+use crate::config::{env_store_dir, get_home_dir};
+use crate::store::Store;
 
 use super::{Error, ErrorKind, Result};
-use git2::{Index, Repository, Signature};
+use git2::{Commit, Index, IndexAddOption, Repository, RepositoryInitOptions, Signature};
 
+/// Resolves the repository of the default store from `RSPASS_STORE_DIR`, then the home
+/// directory.
 pub fn get_repo_path() -> PathBuf {
-    get_home_dir()
-        .unwrap_or_else(|| PathBuf::from("."))
-        .join(if cfg!(target_os = "linux") {
-            ".local/share/rspass"
-        } else {
-            "rspass"
-        })
+    repo_path_from_env(false)
 }
 
-pub fn initialize_repository() -> Result<String> {
-    let folder = get_repo_path();
+/// Resolves a repository from `RSPASS_STORE_DIR`, then `PASSWORD_STORE_DIR` when
+/// `pass_compatibility` is set, then the home directory.
+pub(crate) fn repo_path_from_env(pass_compatibility: bool) -> PathBuf {
+    env_store_dir(pass_compatibility).unwrap_or_else(|| {
+        get_home_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(if cfg!(target_os = "linux") {
+                ".local/share/rspass"
+            } else {
+                "rspass"
+            })
+    })
+}
 
-    Repository::init(&folder).map_err(|err| {
-        Error::new(
-            ErrorKind::InitializationError,
-            format!("failed to initialize repository. {}", err.message()),
-        )
-    })?;
+impl Store {
+    pub fn initialize_repository(&self) -> Result<String> {
+        let folder = self.repo_path();
+        let mut options = RepositoryInitOptions::new();
+        options.initial_head(&self.config()?.git.branch);
+
+        Repository::init_opts(folder, &options).map_err(|err| {
+            Error::new(
+                ErrorKind::InitializationError,
+                format!("failed to initialize repository. {}", err.message()),
+            )
+            .with_source(err)
+        })?;
 
-    Ok(folder.to_str().unwrap().to_owned())
+        Ok(folder.to_string_lossy().into_owned())
+    }
 }
 
-pub(crate) fn open_repository(path: &PathBuf) -> Result<Repository> {
+pub fn initialize_repository() -> Result<String> {
+    Store::default().initialize_repository()
+}
+
+pub(crate) fn open_repository(path: &Path) -> Result<Repository> {
     Repository::open(path).map_err(|err| {
         Error::new(
             ErrorKind::NotInitialized,
@@ -37,6 +57,7 @@ pub(crate) fn open_repository(path: &PathBuf) -> Result<Repository> {
                 err.message()
             ),
         )
+        .with_source(err)
     })
 }
 
@@ -46,38 +67,64 @@ pub(crate) fn get_repo_index(repo: &Repository) -> Result<Index> {
             ErrorKind::InsertionError,
             format!("Failed to obtain repository index. {}", err.message()),
         )
+        .with_source(err)
     })
 }
 
-pub fn commit_changes(
-    repo: &Repository,
+fn commit_error(message: &str, err: git2::Error) -> Error {
+    Error::new(
+        ErrorKind::CommitError,
+        format!("{}. {}", message, err.message()),
+    )
+    .with_source(err)
+}
+
+fn stage_changes(
+    index: &mut Index,
+    workdir: &Path,
     additions: Option<Vec<&str>>,
     removals: Option<Vec<&str>>,
-    message: &str,
 ) -> Result<()> {
-    let mut index = get_repo_index(repo)?;
-
-    if let Some(entries) = additions {
-        entries.iter().for_each(|name| {
+    for name in additions.unwrap_or_default() {
+        if workdir.join(name).is_dir() {
+            index
+                .add_all(
+                    [name],
+                    IndexAddOption::DEFAULT | IndexAddOption::DISABLE_PATHSPEC_MATCH,
+                    None,
+                )
+                .map_err(|err| commit_error("failed to add directory", err))?;
+        } else {
             index
                 .add_path(&PathBuf::from(name))
-                .expect("failed to add file");
-        });
+                .map_err(|err| commit_error("failed to add file", err))?;
+        }
     }
 
-    if let Some(entries) = removals {
-        entries.iter().for_each(|name| {
-            index
-                .remove_path(&PathBuf::from(name))
-                .expect("failed to add file");
-        });
+    for name in removals.unwrap_or_default() {
+        index
+            .remove_path(&PathBuf::from(name))
+            .map_err(|err| commit_error("failed to remove file", err))?;
+        index
+            .remove_dir(&PathBuf::from(name), 0)
+            .map_err(|err| commit_error("failed to remove directory", err))?;
     }
 
-    index.write().unwrap();
+    Ok(())
+}
 
-    let oid = index.write_tree().unwrap();
-    let signature = Signature::now("rspass", "rspass@rspass").unwrap();
-    let tree = repo.find_tree(oid).unwrap();
+fn create_commit(
+    repo: &Repository,
+    index: &mut Index,
+    signature: &Signature,
+    message: &str,
+) -> Result<()> {
+    let oid = index
+        .write_tree()
+        .map_err(|err| commit_error("failed to write tree", err))?;
+    let tree = repo
+        .find_tree(oid)
+        .map_err(|err| commit_error("failed to find tree", err))?;
 
     let parent_commit = match repo.head() {
         Ok(head) => head.peel_to_commit().ok(),
@@ -86,152 +133,465 @@ pub fn commit_changes(
 
     repo.commit(
         Some("HEAD"),
-        &signature,
-        &signature,
+        signature,
+        signature,
         message,
         &tree,
         parent_commit.iter().collect::<Vec<_>>().as_slice(),
     )
-    .unwrap();
+    .map_err(|err| commit_error("failed to commit changes", err))?;
+
+    index.write().map_err(|err| {
+        let error = commit_error("failed to write index", err);
+
+        match reset_head(repo, parent_commit.as_ref()) {
+            Ok(_) => error,
+            Err(reset_err) => Error::new(
+                ErrorKind::CommitError,
+                format!(
+                    "{} HEAD could not be moved back either. {}",
+                    error.message,
+                    reset_err.message()
+                ),
+            )
+            .with_source(error),
+        }
+    })
+}
 
-    Ok(())
+/// Moves the branch HEAD points to back to `parent`, or removes it if there was none.
+fn reset_head(repo: &Repository, parent: Option<&Commit>) -> std::result::Result<(), git2::Error> {
+    let mut head = repo.head()?;
+
+    match parent {
+        Some(parent) => head
+            .set_target(parent.id(), "undo commit after failing to write the index")
+            .map(|_| ()),
+        None => head.delete(),
+    }
 }
 
-pub fn add_remote(uri: &str) -> Result<()> {
-    let repo = open_repository(&get_repo_path())?;
+pub fn commit_changes(
+    repo: &Repository,
+    signature: &Signature,
+    additions: Option<Vec<&str>>,
+    removals: Option<Vec<&str>>,
+    message: &str,
+) -> Result<()> {
+    let mut index = get_repo_index(repo)?;
 
-    repo.remote("origin", uri).map_err(|err| {
-        Error::new(
-            ErrorKind::RemoteError,
-            format!("failed to add remote. {}", err.message()),
-        )
-    })?;
+    let workdir = repo
+        .workdir()
+        .map(|path| path.to_path_buf())
+        .unwrap_or_default();
 
-    Ok(())
+    let result = stage_changes(&mut index, &workdir, additions, removals)
+        .and_then(|_| create_commit(repo, &mut index, signature, message));
+
+    if result.is_err() {
+        let _ = index.read(true);
+    }
+
+    result
 }
 
-pub fn fetch_from_remote(username: &str, token: &str) -> Result<()> {
-    let repo = open_repository(&get_repo_path())?;
+pub struct RemoteInfo {
+    pub name: String,
+    pub url: Option<String>,
+}
+
+fn remote_error(message: &str, err: git2::Error) -> Error {
+    Error::new(
+        ErrorKind::RemoteError,
+        format!("{}. {}", message, err.message()),
+    )
+    .with_source(err)
+}
+
+fn symbolic_error(reference: &str) -> Error {
+    Error::new(
+        ErrorKind::Git,
+        format!("{} does not point to a commit", reference),
+    )
+}
 
-    let mut remote = repo
-        .find_remote("origin")
-        .map_err(|_| Error::new(ErrorKind::RemoteError, "failed to find remote"))?;
+pub(crate) fn get_remote_names(repo: &Repository) -> Result<Vec<String>> {
+    let names = repo
+        .remotes()
+        .map_err(|err| remote_error("failed to list remotes", err))?;
 
-    let mut callbacks = git2::RemoteCallbacks::new();
+    Ok(names.iter().flatten().map(|name| name.to_owned()).collect())
+}
+
+impl Store {
+    pub fn list_remotes(&self) -> Result<Vec<RemoteInfo>> {
+        let repo = self.open()?;
+
+        get_remote_names(&repo)?
+            .into_iter()
+            .map(|name| {
+                let remote = repo
+                    .find_remote(&name)
+                    .map_err(|err| remote_error("failed to find remote", err))?;
+                let url = remote.url().map(|url| url.to_owned());
+
+                Ok(RemoteInfo { name, url })
+            })
+            .collect()
+    }
+
+    pub fn add_remote(&self, uri: &str) -> Result<()> {
+        self.add_named_remote(&self.config()?.git.remote, uri)
+    }
+
+    pub fn add_named_remote(&self, name: &str, uri: &str) -> Result<()> {
+        let repo = self.open()?;
+
+        if repo.find_remote(name).is_ok() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("a remote named {:?} already exists", name),
+            ));
+        }
+
+        repo.remote(name, uri)
+            .map_err(|err| remote_error("failed to add remote", err))?;
+
+        Ok(())
+    }
+
+    pub fn remove_remote(&self, name: &str) -> Result<()> {
+        let repo = self.open()?;
+
+        repo.find_remote(name).map_err(|err| {
+            Error::new(ErrorKind::NotFound, format!("remote {:?} not found", name)).with_source(err)
+        })?;
+
+        repo.remote_delete(name)
+            .map_err(|err| remote_error("failed to remove remote", err))?;
 
-    callbacks.credentials(|_, _, _| git2::Cred::userpass_plaintext(username, token));
+        Ok(())
+    }
+
+    pub fn rename_remote(&self, name: &str, new_name: &str) -> Result<()> {
+        let repo = self.open()?;
 
-    let mut fetch_options = git2::FetchOptions::new();
-    fetch_options.remote_callbacks(callbacks);
+        repo.find_remote(name).map_err(|err| {
+            Error::new(ErrorKind::NotFound, format!("remote {:?} not found", name)).with_source(err)
+        })?;
+
+        if repo.find_remote(new_name).is_ok() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("a remote named {:?} already exists", new_name),
+            ));
+        }
 
-    remote
-        .fetch(&["master"], Some(&mut fetch_options), None)
-        .map_err(|err| {
+        repo.remote_rename(name, new_name)
+            .map_err(|err| remote_error("failed to rename remote", err))?;
+
+        Ok(())
+    }
+
+    pub fn set_remote_url(&self, name: &str, uri: &str) -> Result<()> {
+        let repo = self.open()?;
+
+        repo.find_remote(name).map_err(|err| {
+            Error::new(ErrorKind::NotFound, format!("remote {:?} not found", name)).with_source(err)
+        })?;
+
+        repo.remote_set_url(name, uri)
+            .map_err(|err| remote_error("failed to update remote url", err))?;
+
+        Ok(())
+    }
+
+    pub fn fetch_from_remote(&self, username: &str, token: &str) -> Result<()> {
+        self.fetch_from_named_remote(&self.config()?.git.remote, username, token)
+    }
+
+    pub fn fetch_from_named_remote(&self, name: &str, username: &str, token: &str) -> Result<()> {
+        let repo = self.open()?;
+        let _lock = self.lock(&repo)?;
+        let branch = self.config()?.git.branch;
+
+        let fetch_error = |err: git2::Error| {
             Error::new(
                 ErrorKind::FetchError,
-                format!("failed to fetch master from origin. {}", err.message()),
+                format!(
+                    "failed to fetch {} from {}. {}",
+                    branch,
+                    name,
+                    err.message()
+                ),
             )
+            .with_source(err)
+        };
+
+        let mut remote = repo.find_remote(name).map_err(|err| {
+            Error::new(ErrorKind::RemoteError, "failed to find remote").with_source(err)
         })?;
 
-    let local_branch = repo.find_branch("master", git2::BranchType::Local).unwrap();
-    let local_oid = local_branch.get().target().unwrap();
+        let mut callbacks = git2::RemoteCallbacks::new();
+
+        callbacks.credentials(|_, _, _| git2::Cred::userpass_plaintext(username, token));
+
+        let mut fetch_options = git2::FetchOptions::new();
+        fetch_options.remote_callbacks(callbacks);
+
+        remote
+            .fetch(&[&branch], Some(&mut fetch_options), None)
+            .map_err(fetch_error)?;
+
+        let local_branch = repo
+            .find_branch(&branch, git2::BranchType::Local)
+            .map_err(fetch_error)?;
+        let local_oid = local_branch
+            .get()
+            .target()
+            .ok_or_else(|| symbolic_error(&format!("refs/heads/{}", branch)))?;
+
+        let remote_branch_ref = format!("refs/remotes/{}/{}", name, branch);
+        let remote_branch = repo
+            .find_reference(&remote_branch_ref)
+            .map_err(fetch_error)?;
+        let remote_oid = remote_branch
+            .target()
+            .ok_or_else(|| symbolic_error(&remote_branch_ref))?;
+
+        if local_oid != remote_oid {
+            let annotated_commit = repo
+                .reference_to_annotated_commit(&remote_branch)
+                .map_err(fetch_error)?;
+            let (analysis, _) = repo
+                .merge_analysis(&[&annotated_commit])
+                .map_err(fetch_error)?;
+
+            if analysis.is_fast_forward() {
+                let mut reference = repo
+                    .find_reference(&format!("refs/heads/{}", branch))
+                    .map_err(fetch_error)?;
+                reference
+                    .set_target(remote_oid, "Fast-forward")
+                    .map_err(fetch_error)?;
+                repo.set_head(&format!("refs/heads/{}", branch))
+                    .map_err(fetch_error)?;
+                repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))
+                    .map_err(fetch_error)?;
+            } else if analysis.is_normal() {
+                self.merge_remote_branch(&repo, &branch, name, local_oid, remote_oid)?;
+            }
+        }
 
-    let remote_branch_ref = format!("refs/remotes/origin/{}", "master");
-    let remote_branch = repo.find_reference(&remote_branch_ref).map_err(|err| {
-        Error::new(
-            ErrorKind::FetchError,
-            format!("failed to fetch master from origin. {}", err.message()),
-        )
-    })?;
-    let remote_oid = remote_branch.target().unwrap();
-
-    if local_oid != remote_oid {
-        let annotated_commit =
-            repo.reference_to_annotated_commit(&remote_branch)
-                .map_err(|err| {
-                    Error::new(
-                        ErrorKind::FetchError,
-                        format!("failed to fetch master from origin. {}", err.message()),
-                    )
-                })?;
-        let (analysis, _) = repo.merge_analysis(&[&annotated_commit]).map_err(|err| {
+        Ok(())
+    }
+
+    /// Commits the merge of a diverged remote branch into the local one. Nothing is changed
+    /// if the branches conflict, since encrypted credentials cannot be merged line by line.
+    fn merge_remote_branch(
+        &self,
+        repo: &Repository,
+        branch: &str,
+        remote: &str,
+        local_oid: git2::Oid,
+        remote_oid: git2::Oid,
+    ) -> Result<()> {
+        let merge_error = |err: git2::Error| {
             Error::new(
                 ErrorKind::FetchError,
-                format!("failed to fetch master from origin. {}", err.message()),
+                format!(
+                    "failed to merge {}/{} into {}. {}",
+                    remote,
+                    branch,
+                    branch,
+                    err.message()
+                ),
             )
+            .with_source(err)
+        };
+
+        let local_commit = repo.find_commit(local_oid).map_err(merge_error)?;
+        let remote_commit = repo.find_commit(remote_oid).map_err(merge_error)?;
+        let mut index = repo
+            .merge_commits(&local_commit, &remote_commit, None)
+            .map_err(merge_error)?;
+
+        if index.has_conflicts() {
+            let paths: Vec<String> = index
+                .conflicts()
+                .map_err(merge_error)?
+                .flatten()
+                .filter_map(|conflict| conflict.our.or(conflict.their))
+                .map(|entry| String::from_utf8_lossy(&entry.path).into_owned())
+                .collect();
+
+            return Err(Error::new(
+                ErrorKind::Conflict,
+                format!(
+                    "{} has diverged from {}/{} and both changed {}",
+                    branch,
+                    remote,
+                    branch,
+                    paths.join(", ")
+                ),
+            ));
+        }
+
+        let tree_oid = index.write_tree_to(repo).map_err(merge_error)?;
+        let tree = repo.find_tree(tree_oid).map_err(merge_error)?;
+        let signature = self.signature()?;
+
+        repo.commit(
+            Some(&format!("refs/heads/{}", branch)),
+            &signature,
+            &signature,
+            &format!("merge {}/{}", remote, branch),
+            &tree,
+            &[&local_commit, &remote_commit],
+        )
+        .map_err(merge_error)?;
+
+        repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))
+            .map_err(merge_error)
+    }
+
+    pub fn fetch_from_all_remotes(&self, username: &str, token: &str) -> Result<()> {
+        self.for_each_remote(ErrorKind::FetchError, |name| {
+            self.fetch_from_named_remote(name, username, token)
+        })
+    }
+
+    pub fn push_to_remote(&self, username: &str, token: &str) -> Result<()> {
+        self.push_to_named_remote(&self.config()?.git.remote, username, token)
+    }
+
+    pub fn push_to_named_remote(&self, name: &str, username: &str, token: &str) -> Result<()> {
+        let repo = self.open()?;
+        let branch = self.config()?.git.branch;
+
+        let mut remote = repo.find_remote(name).map_err(|err| {
+            Error::new(ErrorKind::RemoteError, "failed to find remote").with_source(err)
         })?;
 
-        if analysis.is_fast_forward() {
-            let mut reference = repo
-                .find_reference(&format!("refs/heads/{}", "master"))
-                .map_err(|err| {
-                    Error::new(
-                        ErrorKind::FetchError,
-                        format!("failed to fetch master from origin. {}", err.message()),
-                    )
-                })?;
-            reference
-                .set_target(remote_oid, "Fast-forward")
-                .map_err(|err| {
-                    Error::new(
-                        ErrorKind::FetchError,
-                        format!("failed to fetch master from origin. {}", err.message()),
-                    )
-                })?;
-            repo.set_head(&format!("refs/heads/{}", "master"))
-                .map_err(|err| {
-                    Error::new(
-                        ErrorKind::FetchError,
-                        format!("failed to fetch master from origin. {}", err.message()),
-                    )
-                })?;
-            repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))
-                .map_err(|err| {
-                    Error::new(
-                        ErrorKind::FetchError,
-                        format!("failed to fetch master from origin. {}", err.message()),
-                    )
-                })?;
-        } else if analysis.is_normal() {
-            repo.merge(&[&annotated_commit], None, None)
-                .map_err(|err| {
-                    Error::new(
-                        ErrorKind::FetchError,
-                        format!("failed to fetch master from origin. {}", err.message()),
-                    )
-                })?;
-        } else {
-            println!("No merge necessary");
+        let mut callbacks = git2::RemoteCallbacks::new();
+
+        callbacks.credentials(|_, _, _| git2::Cred::userpass_plaintext(username, token));
+
+        let mut push_options = git2::PushOptions::new();
+        push_options.remote_callbacks(callbacks);
+
+        remote
+            .push(
+                &[format!("refs/heads/{0}:refs/heads/{0}", branch)],
+                Some(&mut push_options),
+            )
+            .map_err(|err| {
+                Error::new(
+                    ErrorKind::PushError,
+                    format!("failed to push to {}. {}", name, err.message()),
+                )
+                .with_source(err)
+            })?;
+
+        Ok(())
+    }
+
+    pub fn push_to_all_remotes(&self, username: &str, token: &str) -> Result<()> {
+        self.for_each_remote(ErrorKind::PushError, |name| {
+            self.push_to_named_remote(name, username, token)
+        })
+    }
+
+    pub fn sync_with_remote(&self, name: &str, username: &str, token: &str) -> Result<()> {
+        self.fetch_from_named_remote(name, username, token)?;
+        self.push_to_named_remote(name, username, token)
+    }
+
+    pub fn sync_with_all_remotes(&self, username: &str, token: &str) -> Result<()> {
+        self.fetch_from_all_remotes(username, token)?;
+        self.push_to_all_remotes(username, token)
+    }
+
+    fn for_each_remote<F>(&self, kind: ErrorKind, mut operation: F) -> Result<()>
+    where
+        F: FnMut(&str) -> Result<()>,
+    {
+        let repo = self.open()?;
+        let names = get_remote_names(&repo)?;
+
+        if names.is_empty() {
+            return Err(Error::new(ErrorKind::RemoteError, "no remotes configured"));
+        }
+
+        let mut first_error = None;
+        let mut failures = Vec::new();
+
+        for name in &names {
+            if let Err(err) = operation(name) {
+                failures.push(format!("{}: {}", name, err.message));
+                first_error.get_or_insert(err);
+            }
+        }
+
+        match first_error {
+            None => Ok(()),
+            Some(err) => Err(Error::new(kind, failures.join("; ")).with_source(err)),
         }
     }
-    Ok(())
 }
 
-pub fn push_to_remote(username: &str, token: &str) -> Result<()> {
-    let repo = open_repository(&get_repo_path())?;
+pub fn list_remotes() -> Result<Vec<RemoteInfo>> {
+    Store::default().list_remotes()
+}
 
-    let mut remote = repo
-        .find_remote("origin")
-        .map_err(|_| Error::new(ErrorKind::RemoteError, "failed to find remote"))?;
+pub fn add_remote(uri: &str) -> Result<()> {
+    Store::default().add_remote(uri)
+}
 
-    let mut callbacks = git2::RemoteCallbacks::new();
+pub fn add_named_remote(name: &str, uri: &str) -> Result<()> {
+    Store::default().add_named_remote(name, uri)
+}
 
-    callbacks.credentials(|_, _, _| git2::Cred::userpass_plaintext(username, token));
+pub fn remove_remote(name: &str) -> Result<()> {
+    Store::default().remove_remote(name)
+}
 
-    let mut push_options = git2::PushOptions::new();
-    push_options.remote_callbacks(callbacks);
+pub fn rename_remote(name: &str, new_name: &str) -> Result<()> {
+    Store::default().rename_remote(name, new_name)
+}
 
-    remote
-        .push(
-            &["refs/heads/master:refs/heads/master"],
-            Some(&mut push_options),
-        )
-        .map_err(|err| {
-            Error::new(
-                ErrorKind::PushError,
-                format!("failed to push to remote. {}", err.message()),
-            )
-        })?;
+pub fn set_remote_url(name: &str, uri: &str) -> Result<()> {
+    Store::default().set_remote_url(name, uri)
+}
 
-    Ok(())
+pub fn fetch_from_remote(username: &str, token: &str) -> Result<()> {
+    Store::default().fetch_from_remote(username, token)
+}
+
+pub fn fetch_from_named_remote(name: &str, username: &str, token: &str) -> Result<()> {
+    Store::default().fetch_from_named_remote(name, username, token)
+}
+
+pub fn fetch_from_all_remotes(username: &str, token: &str) -> Result<()> {
+    Store::default().fetch_from_all_remotes(username, token)
+}
+
+pub fn push_to_remote(username: &str, token: &str) -> Result<()> {
+    Store::default().push_to_remote(username, token)
+}
+
+pub fn push_to_named_remote(name: &str, username: &str, token: &str) -> Result<()> {
+    Store::default().push_to_named_remote(name, username, token)
+}
+
+pub fn push_to_all_remotes(username: &str, token: &str) -> Result<()> {
+    Store::default().push_to_all_remotes(username, token)
+}
+
+pub fn sync_with_remote(name: &str, username: &str, token: &str) -> Result<()> {
+    Store::default().sync_with_remote(name, username, token)
+}
+
+pub fn sync_with_all_remotes(username: &str, token: &str) -> Result<()> {
+    Store::default().sync_with_all_remotes(username, token)
 }
@@ -0,0 +1,381 @@
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use git2::{Commit, Delta, DiffFindOptions, Oid, Repository, Sort};
+
+use super::{Credential, CredentialName, Error, ErrorKind, Result};
+use crate::journal::Journal;
+use crate::pgp::decrypt;
+use crate::search_index::index_remove;
+use crate::store::Store;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeKind {
+    Add,
+    Update,
+    Move { from: String },
+    Remove,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldChange {
+    Added {
+        key: String,
+        value: String,
+    },
+    Removed {
+        key: String,
+        value: String,
+    },
+    Changed {
+        key: String,
+        old: String,
+        new: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordChange {
+    pub old: String,
+    pub new: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CredentialDiff {
+    pub password: Option<PasswordChange>,
+    pub fields: Vec<FieldChange>,
+}
+
+const PASSWORD_MASK: &str = "********";
+
+#[derive(Debug, Clone)]
+pub struct CredentialChange {
+    pub oid: String,
+    pub timestamp: DateTime<Utc>,
+    pub author: String,
+    pub message: String,
+    pub path: String,
+    pub kind: ChangeKind,
+}
+
+pub(crate) fn history_error(err: git2::Error) -> Error {
+    Error::new(
+        ErrorKind::HistoryError,
+        format!("failed to read repository history. {}", err.message()),
+    )
+    .with_source(err)
+}
+
+fn find_change(repo: &Repository, commit: &Commit, path: &str) -> Result<Option<ChangeKind>> {
+    let tree = commit.tree().map_err(history_error)?;
+    let parent_tree = match commit.parent(0) {
+        Ok(parent) => Some(parent.tree().map_err(history_error)?),
+        Err(_) => None,
+    };
+
+    let mut diff = repo
+        .diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)
+        .map_err(history_error)?;
+
+    diff.find_similar(Some(DiffFindOptions::new().renames(true)))
+        .map_err(history_error)?;
+
+    let path = Path::new(path);
+
+    for delta in diff.deltas() {
+        let old_path = delta.old_file().path();
+        let new_path = delta.new_file().path();
+
+        let change = match delta.status() {
+            Delta::Added if new_path == Some(path) => ChangeKind::Add,
+            Delta::Modified if new_path == Some(path) => ChangeKind::Update,
+            Delta::Deleted if old_path == Some(path) => ChangeKind::Remove,
+            Delta::Renamed if new_path == Some(path) => ChangeKind::Move {
+                from: old_path
+                    .map(|from| from.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+            },
+            _ => continue,
+        };
+
+        return Ok(Some(change));
+    }
+
+    Ok(None)
+}
+
+impl Store {
+    pub fn credential_history(&self, name: &str) -> Result<Vec<CredentialChange>> {
+        let name = CredentialName::new(name)?;
+        let repo = self.open()?;
+        let mut revwalk = repo.revwalk().map_err(history_error)?;
+
+        revwalk
+            .set_sorting(Sort::TOPOLOGICAL | Sort::TIME)
+            .map_err(history_error)?;
+
+        if revwalk.push_head().is_err() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("no history found for {:?}", name.as_str()),
+            ));
+        }
+
+        let mut path = name.as_str().to_owned();
+        let mut changes = Vec::new();
+
+        for oid in revwalk {
+            let commit = repo
+                .find_commit(oid.map_err(history_error)?)
+                .map_err(history_error)?;
+
+            let Some(kind) = find_change(&repo, &commit, &path)? else {
+                continue;
+            };
+
+            let author = commit.author();
+
+            changes.push(CredentialChange {
+                oid: commit.id().to_string(),
+                timestamp: DateTime::from_timestamp(commit.time().seconds(), 0).unwrap_or_default(),
+                author: format!(
+                    "{} <{}>",
+                    author.name().unwrap_or_default(),
+                    author.email().unwrap_or_default()
+                ),
+                message: commit.message().unwrap_or_default().to_owned(),
+                path: path.clone(),
+                kind: kind.clone(),
+            });
+
+            if let ChangeKind::Move { from } = kind {
+                path = from;
+            }
+        }
+
+        if changes.is_empty() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("no history found for {:?}", name.as_str()),
+            ));
+        }
+
+        Ok(changes)
+    }
+
+    /// Returns the paths `name` was moved from, each with the commit that moved it away. The
+    /// credential may still live under such a path in any commit that does not descend from it.
+    pub(crate) fn previous_paths(&self, name: &str) -> Result<Vec<(String, Oid)>> {
+        let changes = match self.credential_history(name) {
+            Ok(changes) => changes,
+            Err(err) if err.kind == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+
+        changes
+            .into_iter()
+            .filter_map(|change| match change.kind {
+                ChangeKind::Move { from } => Some((from, change.oid)),
+                _ => None,
+            })
+            .map(|(from, oid)| Ok((from, Oid::from_str(&oid).map_err(history_error)?)))
+            .collect()
+    }
+
+    /// Returns the path `name` had at `commit`, following moves recorded in its history.
+    fn path_at(&self, repo: &Repository, name: &CredentialName, commit: &Commit) -> Result<String> {
+        let changes = match self.credential_history(name.as_str()) {
+            Ok(changes) => changes,
+            Err(err) if err.kind == ErrorKind::NotFound => return Ok(name.as_str().to_owned()),
+            Err(err) => return Err(err),
+        };
+
+        for change in changes {
+            let oid = Oid::from_str(&change.oid).map_err(history_error)?;
+
+            if oid == commit.id()
+                || repo
+                    .graph_descendant_of(commit.id(), oid)
+                    .map_err(history_error)?
+            {
+                return Ok(change.path);
+            }
+        }
+
+        Ok(name.as_str().to_owned())
+    }
+
+    /// Reads the credential `name` as it was at `revision`, under the path it had then.
+    pub fn get_credential_at(
+        &self,
+        name: &str,
+        revision: &str,
+        password: &str,
+    ) -> Result<Credential> {
+        let name = CredentialName::new(name)?;
+        let repo = self.open()?;
+        let commit = find_revision(&repo, revision)?;
+        let path = self.path_at(&repo, &name, &commit)?;
+        let buffer = read_blob_at(&repo, &commit, &path)?;
+
+        Ok(Credential::parse(&decrypt(
+            buffer,
+            password,
+            self.private_key()?,
+        )?))
+    }
+
+    /// Writes the ciphertext of `name` as it was at `revision` back under its current name as
+    /// a new commit. The search index entry is dropped and rebuilt on the next search.
+    pub fn restore_credential(&self, name: &str, revision: &str) -> Result<()> {
+        let name = CredentialName::new(name)?;
+        let repo_path = self.repo_path();
+        let repo = self.open()?;
+        let _lock = self.lock(&repo)?;
+        let commit = find_revision(&repo, revision)?;
+        let path = self.path_at(&repo, &name, &commit)?;
+        let buffer = read_blob_at(&repo, &commit, &path)?;
+        let mut journal = Journal::new(repo_path);
+
+        journal.write(name.as_str(), &buffer)?;
+
+        let short_id = commit
+            .as_object()
+            .short_id()
+            .ok()
+            .and_then(|id| id.as_str().map(|id| id.to_owned()))
+            .unwrap_or_else(|| commit.id().to_string());
+
+        journal.commit(
+            &repo,
+            &self.signature()?,
+            Some(vec![name.as_str()]),
+            None,
+            &format!("restore {:?} from {}", name.as_str(), short_id),
+        )?;
+
+        index_remove(self, name.as_str());
+
+        Ok(())
+    }
+
+    pub fn diff_credential(
+        &self,
+        name: &str,
+        from_revision: &str,
+        to_revision: &str,
+        password: &str,
+        reveal_password: bool,
+    ) -> Result<CredentialDiff> {
+        let Credential {
+            password: old_password,
+            fields: old_fields,
+            ..
+        } = self.get_credential_at(name, from_revision, password)?;
+        let Credential {
+            password: new_password,
+            fields: new_fields,
+            ..
+        } = self.get_credential_at(name, to_revision, password)?;
+
+        let password = if old_password == new_password {
+            None
+        } else if reveal_password {
+            Some(PasswordChange {
+                old: old_password,
+                new: new_password,
+            })
+        } else {
+            Some(PasswordChange {
+                old: PASSWORD_MASK.to_owned(),
+                new: PASSWORD_MASK.to_owned(),
+            })
+        };
+
+        let mut fields: Vec<FieldChange> = old_fields
+            .iter()
+            .filter_map(
+                |(key, old)| match new_fields.iter().find(|(new_key, _)| new_key == key) {
+                    None => Some(FieldChange::Removed {
+                        key: key.clone(),
+                        value: old.clone(),
+                    }),
+                    Some((_, new)) if new != old => Some(FieldChange::Changed {
+                        key: key.clone(),
+                        old: old.clone(),
+                        new: new.clone(),
+                    }),
+                    Some(_) => None,
+                },
+            )
+            .collect();
+
+        fields.extend(
+            new_fields
+                .iter()
+                .filter(|(key, _)| !old_fields.iter().any(|(old_key, _)| old_key == key))
+                .map(|(key, value)| FieldChange::Added {
+                    key: key.clone(),
+                    value: value.clone(),
+                }),
+        );
+
+        Ok(CredentialDiff { password, fields })
+    }
+}
+
+pub fn credential_history(name: &str) -> Result<Vec<CredentialChange>> {
+    Store::default().credential_history(name)
+}
+
+pub fn get_credential_at(name: &str, revision: &str, password: &str) -> Result<Credential> {
+    Store::default().get_credential_at(name, revision, password)
+}
+
+pub fn restore_credential(name: &str, revision: &str) -> Result<()> {
+    Store::default().restore_credential(name, revision)
+}
+
+pub fn diff_credential(
+    name: &str,
+    from_revision: &str,
+    to_revision: &str,
+    password: &str,
+    reveal_password: bool,
+) -> Result<CredentialDiff> {
+    Store::default().diff_credential(name, from_revision, to_revision, password, reveal_password)
+}
+
+fn find_revision<'a>(repo: &'a Repository, revision: &str) -> Result<Commit<'a>> {
+    repo.revparse_single(revision)
+        .and_then(|object| object.peel_to_commit())
+        .map_err(|err| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("revision {:?} not found. {}", revision, err.message()),
+            )
+            .with_source(err)
+        })
+}
+
+fn read_blob_at(repo: &Repository, commit: &Commit, name: &str) -> Result<Vec<u8>> {
+    let tree = commit.tree().map_err(history_error)?;
+
+    let entry = tree.get_path(Path::new(name)).map_err(|err| {
+        Error::new(
+            ErrorKind::NotFound,
+            format!("no credential found for {:?} at {}", name, commit.id()),
+        )
+        .with_source(err)
+    })?;
+
+    let blob = repo.find_blob(entry.id()).map_err(|err| {
+        Error::new(
+            ErrorKind::NotFound,
+            format!("{:?} is not a credential", name),
+        )
+        .with_source(err)
+    })?;
+
+    Ok(blob.content().to_vec())
+}
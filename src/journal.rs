@@ -0,0 +1,246 @@
+use std::collections::HashSet;
+use std::fs::{self, create_dir_all, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use git2::{Repository, Signature};
+
+use super::{Error, ErrorKind, Result};
+use crate::git::commit_changes;
+use crate::transfer::remove_empty_parents;
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+enum JournalEntry {
+    Written {
+        path: PathBuf,
+        previous: Option<Vec<u8>>,
+    },
+    Removed {
+        path: PathBuf,
+        previous: Vec<u8>,
+    },
+}
+
+pub(crate) struct Journal {
+    root: PathBuf,
+    entries: Vec<JournalEntry>,
+}
+
+fn map_io_error(err: io::Error, path: &Path) -> Error {
+    match err.kind() {
+        io::ErrorKind::NotFound => {
+            Error::new(ErrorKind::NotFound, format!("{:?} not found", path)).with_source(err)
+        }
+        io::ErrorKind::PermissionDenied => Error::new(
+            ErrorKind::PermissionDenied,
+            format!("You dont have permission to edit {:?}", path),
+        )
+        .with_source(err),
+        _ => Error::new(
+            ErrorKind::Io,
+            format!("failed to write {:?}. {}", path, err),
+        )
+        .with_source(err),
+    }
+}
+
+pub(crate) fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let parent = path.parent().unwrap_or_else(|| Path::new("."));
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let temp_path = parent.join(format!(
+        ".{}.{}-{}.tmp",
+        file_name,
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = File::create_new(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp_path, path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result?;
+
+    if let Ok(directory) = File::open(parent) {
+        let _ = directory.sync_all();
+    }
+
+    Ok(())
+}
+
+impl Journal {
+    pub(crate) fn new(root: &Path) -> Self {
+        Journal {
+            root: root.to_path_buf(),
+            entries: Vec::new(),
+        }
+    }
+
+    pub(crate) fn write(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        let path = self.root.join(name);
+
+        let previous = match fs::read(&path) {
+            Ok(previous) => Some(previous),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(map_io_error(err, &path)),
+        };
+
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent).map_err(|err| map_io_error(err, &path))?;
+        }
+
+        write_atomic(&path, data).map_err(|err| map_io_error(err, &path))?;
+
+        self.entries.push(JournalEntry::Written { path, previous });
+
+        Ok(())
+    }
+
+    pub(crate) fn create(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        if self.root.join(name).exists() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                "A credential already exists with this name",
+            ));
+        }
+
+        self.write(name, data)
+    }
+
+    pub(crate) fn remove(&mut self, name: &str) -> Result<()> {
+        let path = self.root.join(name);
+        let previous = fs::read(&path).map_err(|err| map_io_error(err, &path))?;
+
+        fs::remove_file(&path).map_err(|err| map_io_error(err, &path))?;
+        remove_empty_parents(&self.root, name);
+
+        self.entries.push(JournalEntry::Removed { path, previous });
+
+        Ok(())
+    }
+
+    pub(crate) fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub(crate) fn commit(
+        self,
+        repo: &Repository,
+        signature: &Signature,
+        additions: Option<Vec<&str>>,
+        removals: Option<Vec<&str>>,
+        message: &str,
+    ) -> Result<()> {
+        commit_changes(repo, signature, additions, removals, message)
+            .map_err(|err| self.rollback_after(err))
+    }
+
+    pub(crate) fn commit_all(
+        self,
+        repo: &Repository,
+        signature: &Signature,
+        message: &str,
+    ) -> Result<()> {
+        let mut seen = HashSet::new();
+        let mut additions = Vec::new();
+        let mut removals = Vec::new();
+
+        for entry in &self.entries {
+            let path = match entry {
+                JournalEntry::Written { path, .. } | JournalEntry::Removed { path, .. } => path,
+            };
+
+            if !seen.insert(path) {
+                continue;
+            }
+
+            let name = path
+                .strip_prefix(&self.root)
+                .unwrap_or(path)
+                .to_string_lossy()
+                .into_owned();
+
+            if path.exists() {
+                additions.push(name);
+            } else {
+                removals.push(name);
+            }
+        }
+
+        commit_changes(
+            repo,
+            signature,
+            Some(additions.iter().map(|name| name.as_str()).collect()),
+            Some(removals.iter().map(|name| name.as_str()).collect()),
+            message,
+        )
+        .map_err(|err| self.rollback_after(err))
+    }
+
+    /// Rolls back after `err` and returns it, mentioning the rollback if it failed as well.
+    pub(crate) fn rollback_after(self, err: Error) -> Error {
+        match self.rollback() {
+            Ok(_) => err,
+            Err(rollback_err) => Error::new(
+                err.kind,
+                format!(
+                    "{} Restoring the previous files failed as well. {}",
+                    err.message, rollback_err.message
+                ),
+            )
+            .with_source(err),
+        }
+    }
+
+    /// Restores every file touched by the journal, in reverse order. Restoring continues after
+    /// a failure, and the first error is returned.
+    fn rollback(self) -> Result<()> {
+        let mut result = Ok(());
+
+        for entry in self.entries.into_iter().rev() {
+            let restored = match entry {
+                JournalEntry::Written {
+                    path,
+                    previous: Some(previous),
+                }
+                | JournalEntry::Removed { path, previous } => path
+                    .parent()
+                    .map_or(Ok(()), create_dir_all)
+                    .and_then(|_| write_atomic(&path, &previous))
+                    .map_err(|err| map_io_error(err, &path)),
+                JournalEntry::Written {
+                    path,
+                    previous: None,
+                } => match fs::remove_file(&path) {
+                    Ok(_) => {
+                        if let Ok(name) = path.strip_prefix(&self.root) {
+                            remove_empty_parents(&self.root, &name.to_string_lossy());
+                        }
+
+                        Ok(())
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+                    Err(err) => Err(map_io_error(err, &path)),
+                },
+            };
+
+            if result.is_ok() {
+                result = restored;
+            }
+        }
+
+        result
+    }
+}
@@ -0,0 +1,312 @@
+use std::fs::{self, create_dir, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+
+use super::{Error, ErrorKind, Result};
+use crate::pgp::{
+    derive_keys, generate_key, validate_private_key, validate_pub_key, validate_rsa_pub_key, Keys,
+};
+use crate::store::Store;
+
+/// State of the key files of a store, as reported by [`Store::key_state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyState {
+    /// None of the key files exist.
+    Missing,
+    /// Every key file exists and can be parsed.
+    Complete,
+    /// Some key files are missing, the others can be parsed.
+    Partial,
+    /// At least one key file exists but cannot be parsed.
+    Corrupt,
+}
+
+fn map_io_error(err: io::Error, path: &Path, action: &str) -> Error {
+    match err.kind() {
+        io::ErrorKind::PermissionDenied => Error::new(
+            ErrorKind::PermissionDenied,
+            format!("You dont have permission to {} {:?}", action, path),
+        )
+        .with_source(err),
+        _ => Error::new(
+            ErrorKind::Io,
+            format!("failed to {} {:?}. {}", action, path, err),
+        )
+        .with_source(err),
+    }
+}
+
+pub(crate) type ValidateKey = fn(&str) -> Result<()>;
+
+/// Returns `None` when the key file is missing, otherwise whether it can be parsed.
+pub(crate) fn check_key(path: &Path, validate: ValidateKey) -> Result<Option<bool>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(validate(&contents).is_ok())),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) if err.kind() == io::ErrorKind::InvalidData => Ok(Some(false)),
+        Err(err) => Err(map_io_error(err, path, "read")),
+    }
+}
+
+fn write_key(path: &Path, key: &str) -> Result<()> {
+    File::create_new(path)
+        .and_then(|mut file| file.write_all(key.as_bytes()))
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::AlreadyExists => Error::new(
+                ErrorKind::AlreadyExists,
+                format!("A key already exists at {:?}", path),
+            )
+            .with_source(err),
+            _ => map_io_error(err, path, "write"),
+        })
+}
+
+/// Moves the existing key files into a timestamped `backup-*` folder of the keys directory,
+/// and returns the moves that were made so they can be undone.
+fn backup_keys(keys_dir: &Path, key_paths: &[&PathBuf]) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let backup_dir = keys_dir.join(format!("backup-{}", Utc::now().format("%Y%m%dT%H%M%S%.3f")));
+    let mut moves = Vec::new();
+
+    create_dir(&backup_dir).map_err(|err| map_io_error(err, &backup_dir, "create"))?;
+
+    for path in key_paths.iter().filter(|path| path.exists()) {
+        if let Some(file_name) = path.file_name() {
+            let backup_path = backup_dir.join(file_name);
+
+            if let Err(err) = fs::rename(path, &backup_path) {
+                restore_backup(&moves);
+                return Err(map_io_error(err, path, "back up"));
+            }
+
+            moves.push((path.to_path_buf(), backup_path));
+        }
+    }
+
+    Ok(moves)
+}
+
+/// Moves backed up keys to their original location, after replacing them failed.
+fn restore_backup(moves: &[(PathBuf, PathBuf)]) {
+    for (path, backup_path) in moves {
+        let _ = fs::rename(backup_path, path);
+    }
+}
+
+/// Path next to `path` where a new key is written before it replaces the current one.
+fn temp_key_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".new");
+
+    path.with_file_name(file_name)
+}
+
+/// Writes the new keys to temporary files, then moves the current keys into a backup folder
+/// and the new ones into place. The current keys are put back if anything fails.
+fn replace_keys(keys_dir: &Path, key_files: [(&PathBuf, &str); 3]) -> Result<()> {
+    let mut temp_paths = Vec::new();
+
+    let written = key_files.iter().try_for_each(|(path, key)| {
+        let temp_path = temp_key_path(path);
+        let _ = fs::remove_file(&temp_path);
+
+        write_key(&temp_path, key)?;
+        temp_paths.push(temp_path);
+
+        Ok(())
+    });
+
+    let remove_temp_files = |temp_paths: &[PathBuf]| {
+        temp_paths.iter().for_each(|temp_path| {
+            let _ = fs::remove_file(temp_path);
+        })
+    };
+
+    if let Err(err) = written {
+        remove_temp_files(&temp_paths);
+        return Err(err);
+    }
+
+    let key_paths = key_files.map(|(path, _)| path);
+    let moves = match backup_keys(keys_dir, &key_paths) {
+        Ok(moves) => moves,
+        Err(err) => {
+            remove_temp_files(&temp_paths);
+            return Err(err);
+        }
+    };
+
+    for (path, temp_path) in key_paths.iter().zip(&temp_paths) {
+        if let Err(err) = fs::rename(temp_path, path) {
+            key_paths.iter().for_each(|path| {
+                let _ = fs::remove_file(path);
+            });
+            remove_temp_files(&temp_paths);
+            restore_backup(&moves);
+
+            return Err(map_io_error(err, path, "replace"));
+        }
+    }
+
+    Ok(())
+}
+
+impl Store {
+    /// Inspects the key files without unlocking the private key.
+    pub fn key_state(&self) -> Result<KeyState> {
+        let (pub_key_path, private_key_path, rsa_pub_key_path) = self.key_files()?;
+        let checks = [
+            check_key(&pub_key_path, validate_pub_key)?,
+            check_key(&private_key_path, validate_private_key)?,
+            check_key(&rsa_pub_key_path, validate_rsa_pub_key)?,
+        ];
+
+        Ok(if checks.contains(&Some(false)) {
+            KeyState::Corrupt
+        } else if checks.iter().all(Option::is_none) {
+            KeyState::Missing
+        } else if checks.iter().all(Option::is_some) {
+            KeyState::Complete
+        } else {
+            KeyState::Partial
+        })
+    }
+
+    /// Generates the keys of the store.
+    ///
+    /// Fails if any key file already exists, unless `force` is set, in which case the new keys
+    /// are generated and written first, then the existing files are moved into a `backup-*`
+    /// folder of the keys directory. Credentials encrypted for the old keys can only be read
+    /// with the backed up private key.
+    pub fn generate_keys(
+        &self,
+        name: &str,
+        email: &str,
+        password: &str,
+        force: bool,
+    ) -> Result<String> {
+        let keys_dir = self.keys_path()?.to_path_buf();
+        let (pub_key_path, private_key_path, rsa_pub_key_path) = self.key_files()?;
+
+        match create_dir(&keys_dir) {
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(err) => return Err(map_io_error(err, &keys_dir, "create")),
+        };
+
+        let replace = match self.key_state()? {
+            KeyState::Missing => false,
+            _ if force => true,
+            KeyState::Complete => {
+                return Err(Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!(
+                        "keys already exist in {:?}, use force to replace them",
+                        keys_dir
+                    ),
+                ))
+            }
+            KeyState::Partial => {
+                return Err(Error::new(
+                    ErrorKind::Corrupt,
+                    format!(
+                        "keys in {:?} are incomplete, restore them with repair_keys or use force to replace them",
+                        keys_dir
+                    ),
+                ))
+            }
+            KeyState::Corrupt => {
+                return Err(Error::new(
+                    ErrorKind::Corrupt,
+                    format!(
+                        "keys in {:?} are corrupt, use force to replace them",
+                        keys_dir
+                    ),
+                ))
+            }
+        };
+
+        let Keys {
+            pub_key,
+            private_key,
+            rsa_pub_key,
+        } = generate_key(name, email, password)?;
+
+        if replace {
+            replace_keys(
+                &keys_dir,
+                [
+                    (&pub_key_path, &pub_key),
+                    (&private_key_path, &private_key),
+                    (&rsa_pub_key_path, &rsa_pub_key),
+                ],
+            )?;
+        } else {
+            write_key(&pub_key_path, &pub_key)?;
+            write_key(&private_key_path, &private_key)?;
+            write_key(&rsa_pub_key_path, &rsa_pub_key)?;
+        }
+
+        Ok(keys_dir.to_string_lossy().into_owned())
+    }
+
+    /// Recreates missing public keys from the private key, which is unlocked with `password`.
+    /// Existing files are never modified.
+    pub fn repair_keys(&self, password: &str) -> Result<()> {
+        let (pub_key_path, private_key_path, rsa_pub_key_path) = self.key_files()?;
+
+        match self.key_state()? {
+            KeyState::Complete => return Ok(()),
+            KeyState::Partial => {}
+            KeyState::Missing => {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    "no keys found, create them with generate_keys",
+                ))
+            }
+            KeyState::Corrupt => {
+                return Err(Error::new(
+                    ErrorKind::Corrupt,
+                    "keys are corrupt, replace them with generate_keys and force",
+                ))
+            }
+        }
+
+        if !private_key_path.exists() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "the private key {:?} is missing, replace the keys with generate_keys and force",
+                    private_key_path
+                ),
+            ));
+        }
+
+        let Keys {
+            pub_key,
+            rsa_pub_key,
+            ..
+        } = derive_keys(&self.private_key()?, password)?;
+
+        for (path, key) in [(&pub_key_path, pub_key), (&rsa_pub_key_path, rsa_pub_key)] {
+            if !path.exists() {
+                write_key(path, &key)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn key_state() -> Result<KeyState> {
+    Store::default().key_state()
+}
+
+pub fn generate_keys(name: &str, email: &str, password: &str, force: bool) -> Result<String> {
+    Store::default().generate_keys(name, email, password, force)
+}
+
+pub fn repair_keys(password: &str) -> Result<()> {
+    Store::default().repair_keys(password)
+}
@@ -1,27 +1,81 @@
-use config::get_config_dir;
-use git::{commit_changes, open_repository};
-use pgp::{decrypt, recover_private_key, recover_rsa_pub_key, Keys};
+use pgp::decrypt;
 use rand::distributions::Alphanumeric;
 use rand::prelude::SliceRandom;
-use rand::seq::IteratorRandom;
 use rand::Rng;
-use std::collections::HashMap;
-use std::fs::{self, create_dir, create_dir_all, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
-use std::path::PathBuf;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::{fs::File, io};
+use transaction::apply_edit;
 
 pub use git::{
-    add_remote, fetch_from_remote, get_repo_path, initialize_repository, push_to_remote,
+    add_named_remote, add_remote, fetch_from_all_remotes, fetch_from_named_remote,
+    fetch_from_remote, get_repo_path, initialize_repository, list_remotes, push_to_all_remotes,
+    push_to_named_remote, push_to_remote, remove_remote, rename_remote, set_remote_url,
+    sync_with_all_remotes, sync_with_remote, RemoteInfo,
 };
 
-pub use config::{set_config_dir, set_home_dir};
+pub use attachment::{add_attachment, get_attachment, list_attachments, remove_attachment};
 
+pub use config::{
+    get_store_config, set_config_dir, set_home_dir, Backend, GeneratorConfig, GitConfig,
+    KeysConfig, StoreConfig, CONFIG_DIR_ENV, KEY_ENV, PASS_STORE_DIR_ENV, PASS_STORE_KEY_ENV,
+    STORE_DIR_ENV,
+};
+
+pub use credential::Credential;
+
+pub use name::CredentialName;
+
+pub use history::{
+    credential_history, diff_credential, get_credential_at, restore_credential, ChangeKind,
+    CredentialChange, CredentialDiff, FieldChange, PasswordChange,
+};
+
+pub use keys::{generate_keys, key_state, repair_keys, KeyState};
+
+pub use list::{credential_tree, list_credentials, CredentialNode};
+
+pub use otp::get_otp;
+
+pub use profile::{copy_credential_to_profile, create_profile, list_profiles, open_profile};
+
+pub use rewrite::{purge_credential, squash_history, RewriteReport};
+
+pub use search::{search, SearchMatch};
+
+pub use search_index::rebuild_index;
+
+pub use store::Store;
+
+pub use transaction::Transaction;
+
+pub use verify::{verify_store, Check, Issue, Severity, VerifyReport};
+
+mod attachment;
 mod config;
+mod credential;
 mod git;
+mod history;
+mod journal;
+mod keys;
+mod list;
+mod lock;
+mod name;
+mod otp;
 mod pgp;
-
-#[derive(Debug)]
+mod profile;
+mod rewrite;
+mod search;
+mod search_index;
+mod store;
+mod transaction;
+mod transfer;
+mod verify;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum ErrorKind {
     InitializationError,
     RemoteError,
@@ -38,12 +92,25 @@ pub enum ErrorKind {
     DecryptationError,
     NotFound,
     ConfigAlreadySet,
+    HistoryError,
+    OtpError,
+    InvalidName,
+    CommitError,
+    Locked,
+    Io,
+    Git,
+    Crypto,
+    Corrupt,
+    Conflict,
 }
 
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
 #[derive(Debug)]
 pub struct Error {
     pub kind: ErrorKind,
     pub message: String,
+    source: Option<BoxError>,
 }
 
 impl Error {
@@ -51,48 +118,81 @@ impl Error {
         Error {
             kind,
             message: message.into(),
+            source: None,
         }
     }
+
+    /// Keeps `source` as the underlying cause, returned by [`std::error::Error::source`].
+    pub(crate) fn with_source(mut self, source: impl Into<BoxError>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|source| source as &(dyn std::error::Error + 'static))
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-pub fn get_config_path() -> PathBuf {
-    get_config_dir()
-        .expect("config_dir should already be defined")
-        .join("rspass")
+/// Resolves the config directory of the default store from `RSPASS_CONFIG_DIR`, then the
+/// `rspass` folder inside the config directory.
+pub fn get_config_path() -> Result<PathBuf> {
+    Store::default().config_path().map(Path::to_path_buf)
 }
 
-fn get_credential_file(path: &PathBuf, write_mode: bool) -> Result<File> {
+fn get_credential_file(path: &Path) -> Result<File> {
     OpenOptions::new()
         .read(true)
-        .write(write_mode)
         .open(path)
         .map_err(|err| match err.kind() {
             io::ErrorKind::NotFound => Error::new(
                 ErrorKind::NotFound,
                 format!("no credential found for {:?}", path).as_str(),
-            ),
-            _ => panic!("unexpected error while reading credential"),
+            )
+            .with_source(err),
+            _ => Error::new(
+                ErrorKind::Io,
+                format!("failed to open credential {:?}. {}", path, err),
+            )
+            .with_source(err),
         })
 }
 
 pub fn generate_password(length: usize) -> String {
+    build_password(length, true)
+}
+
+fn random_char(chars: &str) -> char {
+    let chars = chars.as_bytes();
+
+    chars[rand::thread_rng().gen_range(0..chars.len())] as char
+}
+
+fn build_password(length: usize, symbols: bool) -> String {
     let uppercase = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
     let lowercase = "abcdefghijklmnopqrstuvwxyz";
     let digits = "0123456789";
     let special_chars = "!@#$%^&*()";
 
     let mut password = String::new();
-    password.push(uppercase.chars().choose(&mut rand::thread_rng()).unwrap());
-    password.push(lowercase.chars().choose(&mut rand::thread_rng()).unwrap());
-    password.push(digits.chars().choose(&mut rand::thread_rng()).unwrap());
-    password.push(
-        special_chars
-            .chars()
-            .choose(&mut rand::thread_rng())
-            .unwrap(),
-    );
+    password.push(random_char(uppercase));
+    password.push(random_char(lowercase));
+    password.push(random_char(digits));
+
+    if symbols {
+        password.push(random_char(special_chars));
+    }
 
     let remaining_length = length.saturating_sub(password.len());
     let additional_chars: String = rand::thread_rng()
@@ -110,124 +210,80 @@ pub fn generate_password(length: usize) -> String {
     password
 }
 
-pub fn generate_keys(name: &str, email: &str, password: &str) -> Result<String> {
-    let config_dir = get_config_path();
-
-    match create_dir(&config_dir) {
-        Ok(_) => {
-            let Keys {
-                pub_key,
-                private_key,
-                rsa_pub_key,
-            } = pgp::generate_key(name, email, password)?;
-
-            File::create_new(config_dir.clone().join("rspass.pub"))
-                .unwrap()
-                .write_all(pub_key.as_bytes())
-                .unwrap();
-
-            File::create_new(config_dir.clone().join("rspass.key"))
-                .unwrap()
-                .write_all(private_key.as_bytes())
-                .unwrap();
-
-            File::create_new(config_dir.clone().join("rspass.pem"))
-                .unwrap()
-                .write_all(rsa_pub_key.as_bytes())
-                .unwrap();
-        }
-        Err(err) => match err.kind() {
-            io::ErrorKind::AlreadyExists => {}
-            io::ErrorKind::PermissionDenied => {
-                return Err(Error::new(
-                    ErrorKind::PermissionDenied,
-                    "You dont have permission to create the config folder",
-                ));
-            }
-            _ => panic!("failed to create config folder"),
-        },
-    };
+impl Store {
+    /// Generates a password using the `generator` settings of the store.
+    pub fn generate_password(&self) -> Result<String> {
+        let GeneratorConfig { length, symbols } = self.config()?.generator;
 
-    Ok(config_dir.to_str().unwrap().to_owned())
-}
+        Ok(build_password(length, symbols))
+    }
 
-pub fn insert_credential(
-    name: &str,
-    password: &str,
-    metadata: Option<Vec<(String, String)>>,
-) -> Result<()> {
-    let repo_path = get_repo_path();
-    let repository = open_repository(&repo_path)?;
-    let file_path = repo_path.join(name);
-
-    create_dir_all(file_path.as_path().parent().unwrap()).map_err(|err| match err.kind() {
-        io::ErrorKind::PermissionDenied => Error::new(
-            ErrorKind::PermissionDenied,
-            "You dont have permission to create a subdirectory",
-        ),
-        io::ErrorKind::AlreadyExists => Error::new(
-            ErrorKind::AlreadyExists,
-            "A credential already exists with this name",
-        ),
-        _ => panic!("Unexpected error while creating credentials directories"),
-    })?;
-
-    let pub_key = recover_rsa_pub_key()?;
-    let mut file_data = String::new();
-
-    let mut file = File::create_new(&file_path).map_err(|err| match err.kind() {
-        io::ErrorKind::AlreadyExists => Error::new(
-            ErrorKind::AlreadyExists,
-            "A credential already exists with this name",
-        ),
-        io::ErrorKind::PermissionDenied => Error::new(
-            ErrorKind::PermissionDenied,
-            "You dont have permission to edit the repository",
-        ),
-        _ => panic!("Unexpected error while creating credentials file"),
-    })?;
-
-    file_data.push_str(password);
-
-    if let Some(data) = metadata {
-        data.iter().for_each(|(key, value)| {
-            file_data.push_str(format!("\n{key}={value}").as_str());
-        });
+    pub fn insert_credential(&self, name: &str, credential: &Credential) -> Result<()> {
+        let mut transaction = Transaction::new();
+
+        transaction.insert(name, credential)?;
+        transaction.commit_to(self, &format!("add {:?}", name))
     }
 
-    file.write_all(pgp::encrypt(file_data, pub_key)?.as_ref())
-        .expect("failed to write credentials");
+    pub fn get_credential(&self, name: &str, password: &str) -> Result<Credential> {
+        let name = CredentialName::new(name)?;
 
-    commit_changes(
-        &repository,
-        Some(vec![name]),
-        None,
-        &format!("add {:?}", name),
-    )
-}
+        read_credential(self, &name.path_in(self.repo_path()), password)
+    }
 
-pub fn get_credential(name: &str, password: &str, full: bool) -> Result<String> {
-    let private_key = recover_private_key()?;
+    pub fn edit_credential(
+        &self,
+        name: &str,
+        gpg_password: &str,
+        password: Option<&str>,
+        metadata: Option<Vec<(String, Option<String>)>>,
+        notes: Option<&str>,
+    ) -> Result<Credential> {
+        let name = CredentialName::new(name)?;
+        let _lock = self.lock(&self.open()?)?;
+        let mut credential = read_credential(self, &name.path_in(self.repo_path()), gpg_password)?;
+
+        apply_edit(&mut credential, password, metadata, notes);
+        self.replace_credential(name.as_str(), &credential)?;
+
+        Ok(credential)
+    }
 
-    let path = get_repo_path().join(name);
-    let mut buffer = Vec::new();
+    pub fn replace_credential(&self, name: &str, credential: &Credential) -> Result<()> {
+        let mut transaction = Transaction::new();
 
-    get_credential_file(&path, false)?
-        .read_to_end(&mut buffer)
-        .map_err(|err| match err.kind() {
-            std::io::ErrorKind::InvalidData => {
-                Error::new(ErrorKind::BadConfig, "Invalid credential data")
-            }
-            _ => panic!("unexpected error while reading credential"),
-        })?;
+        transaction.replace(name, credential)?;
+        transaction.commit_to(self, &format!("update {:?}", name))
+    }
+
+    pub fn remove_credential(&self, name: &str, recursive: bool) -> Result<()> {
+        let mut transaction = Transaction::new();
+
+        transaction.remove(name, recursive)?;
+        transaction.commit_to(self, &format!("remove {:?}", name))
+    }
 
-    let credentials = decrypt(buffer, password, private_key)?;
+    pub fn move_credential(&self, target: &str, destination: &str, force: bool) -> Result<()> {
+        let mut transaction = Transaction::new();
 
-    if full {
-        Ok(credentials)
-    } else {
-        Ok(credentials.lines().next().unwrap().to_owned())
+        transaction.move_credential(target, destination, force)?;
+        transaction.commit_to(self, &format!("move {} to {}", target, destination))
     }
+
+    pub fn copy_credential(&self, target: &str, destination: &str, force: bool) -> Result<()> {
+        let mut transaction = Transaction::new();
+
+        transaction.copy_credential(target, destination, force)?;
+        transaction.commit_to(self, &format!("copy {} to {}", target, destination))
+    }
+}
+
+pub fn insert_credential(name: &str, credential: &Credential) -> Result<()> {
+    Store::default().insert_credential(name, credential)
+}
+
+pub fn get_credential(name: &str, password: &str) -> Result<Credential> {
+    Store::default().get_credential(name, password)
 }
 
 pub fn edit_credential(
@@ -235,129 +291,43 @@ pub fn edit_credential(
     gpg_password: &str,
     password: Option<&str>,
     metadata: Option<Vec<(String, Option<String>)>>,
-) -> Result<()> {
-    let repo_path = get_repo_path();
-    let file_path = repo_path.join(name);
-    let mut buffer = Vec::new();
-    let mut new_credential = String::new();
-    let mut file = get_credential_file(&file_path, true)?;
-
-    file.read_to_end(&mut buffer)
-        .map_err(|err| match err.kind() {
-            std::io::ErrorKind::InvalidData => {
-                Error::new(ErrorKind::BadConfig, "Invalid credential data")
-            }
-            _ => panic!("unexpected error while reading credential"),
-        })?;
-
-    let pub_key = recover_rsa_pub_key()?;
-    let private_key = recover_private_key()?;
-    let credential = decrypt(buffer, gpg_password, private_key)?;
-
-    match password {
-        Some(pass) => new_credential.push_str(pass),
-        None => new_credential.push_str(credential.lines().next().unwrap()),
-    };
-
-    let mut data: HashMap<String, String> = credential
-        .lines()
-        .filter_map(|line| {
-            let mut split = line.splitn(2, '=');
-            let key = split.next()?.to_owned();
-            let value = split.next()?.to_owned();
-            Some((key, value))
-        })
-        .collect();
-
-    if let Some(metadata) = metadata {
-        metadata.into_iter().for_each(|item| {
-            let (key, value) = item;
-
-            match value {
-                Some(inner_value) => {
-                    data.insert(key, inner_value);
-                }
-                None => {
-                    data.remove(key.as_str());
-                }
-            };
-        });
-    }
-
-    data.iter().for_each(|(key, value)| {
-        new_credential.push_str(&format!("\n{}={}", key, value));
-    });
+    notes: Option<&str>,
+) -> Result<Credential> {
+    Store::default().edit_credential(name, gpg_password, password, metadata, notes)
+}
 
-    file.seek(SeekFrom::Start(0)).unwrap();
-    file.write_all(pgp::encrypt(new_credential, pub_key)?.as_ref())
-        .expect("failed to write credentials");
+pub fn replace_credential(name: &str, credential: &Credential) -> Result<()> {
+    Store::default().replace_credential(name, credential)
+}
 
-    let repository = open_repository(&repo_path)?;
+pub fn remove_credential(name: &str, recursive: bool) -> Result<()> {
+    Store::default().remove_credential(name, recursive)
+}
 
-    commit_changes(
-        &repository,
-        Some(vec![name]),
-        None,
-        &format!("update {:?}", name),
-    )
+pub fn move_credential(target: &str, destination: &str, force: bool) -> Result<()> {
+    Store::default().move_credential(target, destination, force)
 }
 
-pub fn remove_credential(name: &str) -> Result<()> {
-    let repo_path = get_repo_path();
-    let file_path = repo_path.join(name);
-
-    fs::remove_file(file_path).map_err(|err| match err.kind() {
-        io::ErrorKind::NotFound => Error::new(ErrorKind::NotFound, "credential not found"),
-        io::ErrorKind::PermissionDenied => Error::new(
-            ErrorKind::PermissionDenied,
-            "You dont have permission to remove this credential",
-        ),
-        io::ErrorKind::InvalidInput => Error::new(ErrorKind::NotFound, "credential not found"),
-        _ => panic!("unexpected error while removing credential"),
-    })?;
-
-    let repository = open_repository(&repo_path)?;
-
-    commit_changes(
-        &repository,
-        None,
-        Some(vec![name]),
-        &format!("remove {:?}", name),
-    )
+pub fn copy_credential(target: &str, destination: &str, force: bool) -> Result<()> {
+    Store::default().copy_credential(target, destination, force)
 }
 
-pub fn move_credential(target: &str, destination: &str) -> Result<()> {
-    let repo_path = get_repo_path();
-    let target_path = repo_path.join(target);
-    let destination_path = repo_path.join(destination);
-
-    create_dir_all(destination_path.parent().unwrap()).map_err(|err| match err.kind() {
-        io::ErrorKind::PermissionDenied => Error::new(
-            ErrorKind::PermissionDenied,
-            "You dont have permission to create a subdirectory",
-        ),
-        io::ErrorKind::AlreadyExists => Error::new(
-            ErrorKind::AlreadyExists,
-            "A credential already exists with this name",
-        ),
-        _ => panic!("Unexpected error while creating credentials directories"),
-    })?;
-
-    fs::rename(&target_path, &destination_path).map_err(|err| match err.kind() {
-        io::ErrorKind::NotFound => Error::new(ErrorKind::NotFound, "credential not found"),
-        io::ErrorKind::PermissionDenied => Error::new(
-            ErrorKind::PermissionDenied,
-            "You dont have permission to move this credential",
-        ),
-        _ => panic!("unexpected error while moving credential"),
-    })?;
-
-    let repository = open_repository(&repo_path)?;
-
-    commit_changes(
-        &repository,
-        Some(vec![destination]),
-        Some(vec![target]),
-        &format!("move {} to {}", target, destination),
-    )
+fn read_credential(store: &Store, path: &Path, password: &str) -> Result<Credential> {
+    let private_key = store.private_key()?;
+    let mut buffer = Vec::new();
+
+    get_credential_file(path)?
+        .read_to_end(&mut buffer)
+        .map_err(|err| match err.kind() {
+            std::io::ErrorKind::InvalidData => {
+                Error::new(ErrorKind::BadConfig, "Invalid credential data").with_source(err)
+            }
+            _ => Error::new(
+                ErrorKind::Io,
+                format!("failed to read credential {:?}. {}", path, err),
+            )
+            .with_source(err),
+        })?;
+
+    Ok(Credential::parse(&decrypt(buffer, password, private_key)?))
 }
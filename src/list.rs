@@ -0,0 +1,121 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::{Error, ErrorKind, Result};
+use crate::store::Store;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CredentialNode {
+    Folder {
+        name: String,
+        children: Vec<CredentialNode>,
+    },
+    Credential {
+        name: String,
+        path: String,
+    },
+}
+
+impl CredentialNode {
+    pub fn name(&self) -> &str {
+        match self {
+            CredentialNode::Folder { name, .. } => name,
+            CredentialNode::Credential { name, .. } => name,
+        }
+    }
+}
+
+fn read_folder(folder: &Path, prefix: &str) -> Result<Vec<CredentialNode>> {
+    let entries = fs::read_dir(folder).map_err(|err| match err.kind() {
+        io::ErrorKind::PermissionDenied => Error::new(
+            ErrorKind::PermissionDenied,
+            "You dont have permission to read the repository",
+        )
+        .with_source(err),
+        _ => Error::new(
+            ErrorKind::Io,
+            format!("failed to list credentials in {:?}. {}", folder, err),
+        )
+        .with_source(err),
+    })?;
+
+    let mut nodes = Vec::new();
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+
+        if name.starts_with('.') {
+            continue;
+        }
+
+        let path = format!("{}{}", prefix, name);
+        let file_type = match entry.file_type() {
+            Ok(file_type) => file_type,
+            Err(_) => continue,
+        };
+
+        if file_type.is_dir() {
+            let children = read_folder(&entry.path(), &format!("{}/", path))?;
+            nodes.push(CredentialNode::Folder { name, children });
+        } else if file_type.is_file() {
+            nodes.push(CredentialNode::Credential { name, path });
+        }
+    }
+
+    nodes.sort_by(|a, b| match (a, b) {
+        (CredentialNode::Folder { .. }, CredentialNode::Credential { .. }) => {
+            std::cmp::Ordering::Less
+        }
+        (CredentialNode::Credential { .. }, CredentialNode::Folder { .. }) => {
+            std::cmp::Ordering::Greater
+        }
+        _ => a.name().cmp(b.name()),
+    });
+
+    Ok(nodes)
+}
+
+fn collect_paths(nodes: &[CredentialNode], paths: &mut Vec<String>) {
+    nodes.iter().for_each(|node| match node {
+        CredentialNode::Folder { children, .. } => collect_paths(children, paths),
+        CredentialNode::Credential { path, .. } => paths.push(path.clone()),
+    });
+}
+
+impl Store {
+    pub fn credential_tree(&self) -> Result<Vec<CredentialNode>> {
+        let repo_path = self.repo_path();
+        self.open()?;
+
+        read_folder(repo_path, "")
+    }
+
+    pub fn list_credentials(&self, prefix: Option<&str>) -> Result<Vec<String>> {
+        let mut paths = Vec::new();
+        collect_paths(&self.credential_tree()?, &mut paths);
+
+        // The prefix selects a credential or folder, so `site` matches `site/mail` but not
+        // `sites/mail`.
+        if let Some(prefix) = prefix.map(|prefix| prefix.trim_end_matches('/')) {
+            if !prefix.is_empty() {
+                paths.retain(|path| {
+                    path.strip_prefix(prefix)
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+                });
+            }
+        }
+
+        paths.sort();
+
+        Ok(paths)
+    }
+}
+
+pub fn credential_tree() -> Result<Vec<CredentialNode>> {
+    Store::default().credential_tree()
+}
+
+pub fn list_credentials(prefix: Option<&str>) -> Result<Vec<String>> {
+    Store::default().list_credentials(prefix)
+}
@@ -0,0 +1,108 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use git2::Repository;
+
+use super::{Error, ErrorKind, Result};
+
+const LOCK_FILE: &str = "rspass.lock";
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+thread_local! {
+    /// Lock files held by this thread, with the number of [`StoreLock`]s sharing each of them.
+    static HELD_LOCKS: RefCell<HashMap<PathBuf, (usize, File)>> = RefCell::new(HashMap::new());
+}
+
+pub(crate) struct StoreLock {
+    path: PathBuf,
+}
+
+fn lock_error(err: io::Error, path: &Path) -> Error {
+    match err.kind() {
+        io::ErrorKind::PermissionDenied => Error::new(
+            ErrorKind::PermissionDenied,
+            format!(
+                "You dont have permission to create the lock file {:?}",
+                path
+            ),
+        )
+        .with_source(err),
+        _ => Error::new(ErrorKind::Io, format!("failed to lock {:?}. {}", path, err))
+            .with_source(err),
+    }
+}
+
+/// Takes an exclusive lock on the store's lock file, waiting up to `timeout` for another
+/// process to release it.
+///
+/// The lock is an advisory lock of the operating system rather than the existence of the
+/// file, so it is released when the holding process exits, even if it crashed, and the file
+/// itself is never removed. Locks are reentrant within a thread.
+pub(crate) fn lock_store(repo: &Repository, timeout: Duration) -> Result<StoreLock> {
+    let path = repo.path().join(LOCK_FILE);
+
+    let reentered = HELD_LOCKS.with_borrow_mut(|held| match held.get_mut(&path) {
+        Some((count, _)) => {
+            *count += 1;
+            true
+        }
+        None => false,
+    });
+
+    if reentered {
+        return Ok(StoreLock { path });
+    }
+
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .map_err(|err| lock_error(err, &path))?;
+    let started = Instant::now();
+
+    loop {
+        match file.try_lock() {
+            Ok(_) => break,
+            Err(TryLockError::WouldBlock) if started.elapsed() < timeout => {
+                thread::sleep(RETRY_INTERVAL)
+            }
+            Err(TryLockError::WouldBlock) => {
+                return Err(Error::new(
+                    ErrorKind::Locked,
+                    "the store is locked by another process",
+                ))
+            }
+            Err(TryLockError::Error(err)) => return Err(lock_error(err, &path)),
+        }
+    }
+
+    HELD_LOCKS.with_borrow_mut(|held| held.insert(path.clone(), (1, file)));
+
+    Ok(StoreLock { path })
+}
+
+impl Drop for StoreLock {
+    fn drop(&mut self) {
+        HELD_LOCKS.with_borrow_mut(|held| {
+            let released = match held.get_mut(&self.path) {
+                Some((count, _)) => {
+                    *count -= 1;
+                    *count == 0
+                }
+                None => false,
+            };
+
+            if released {
+                if let Some((_, file)) = held.remove(&self.path) {
+                    let _ = file.unlock();
+                }
+            }
+        });
+    }
+}
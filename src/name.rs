@@ -0,0 +1,185 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::{Error, ErrorKind, Result};
+use crate::attachment::ATTACHMENTS_DIR;
+use crate::config::STORE_CONFIG_FILE;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CredentialName(String);
+
+fn invalid_name(name: &str, reason: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidName,
+        format!("invalid credential name {:?}: {}", name, reason),
+    )
+}
+
+/// Whether `segment` is one of the files the store keeps next to the credentials, or a
+/// temporary file left by an interrupted write.
+fn is_reserved(segment: &str) -> bool {
+    segment == ATTACHMENTS_DIR
+        || segment == STORE_CONFIG_FILE
+        || segment
+            .strip_prefix('.')
+            .is_some_and(|rest| rest.ends_with(".tmp"))
+}
+
+impl CredentialName {
+    pub fn new(name: &str) -> Result<Self> {
+        if name.is_empty() {
+            return Err(invalid_name(name, "name is empty"));
+        }
+
+        if name.contains('\0') {
+            return Err(invalid_name(name, "name contains a NUL byte"));
+        }
+
+        if name.contains('\\') {
+            return Err(invalid_name(name, "name contains a backslash"));
+        }
+
+        if name.starts_with('/') || Path::new(name).is_absolute() {
+            return Err(invalid_name(name, "name must be relative to the store"));
+        }
+
+        for segment in name.split('/') {
+            match segment {
+                "" => return Err(invalid_name(name, "name contains an empty segment")),
+                "." | ".." => return Err(invalid_name(name, "name contains a relative segment")),
+                ".git" => return Err(invalid_name(name, "name points inside the git directory")),
+                _ if is_reserved(segment) => {
+                    return Err(invalid_name(
+                        name,
+                        &format!("{:?} is reserved by the store", segment),
+                    ))
+                }
+                _ => {}
+            }
+        }
+
+        Ok(CredentialName(name.to_owned()))
+    }
+
+    pub(crate) fn segment(name: &str) -> Result<Self> {
+        if name.contains('/') {
+            return Err(invalid_name(name, "name must be a single segment"));
+        }
+
+        Self::new(name)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub(crate) fn path_in(&self, root: &Path) -> PathBuf {
+        root.join(&self.0)
+    }
+}
+
+impl AsRef<str> for CredentialName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for CredentialName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl TryFrom<&str> for CredentialName {
+    type Error = Error;
+
+    fn try_from(name: &str) -> Result<Self> {
+        CredentialName::new(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_invalid(name: &str) {
+        let err = CredentialName::new(name).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidName, "{:?}", name);
+    }
+
+    #[test]
+    fn accepts_nested_names() {
+        assert_eq!(
+            CredentialName::new("web/mail.example").unwrap().as_str(),
+            "web/mail.example"
+        );
+        assert_eq!(CredentialName::new("a..b").unwrap().as_str(), "a..b");
+    }
+
+    #[test]
+    fn accepts_hidden_names_that_are_not_reserved() {
+        for name in [".env", "ssh/.config", ".tmp", "web/.attachments.bak"] {
+            assert_eq!(CredentialName::new(name).unwrap().as_str(), name);
+        }
+    }
+
+    #[test]
+    fn rejects_relative_segments() {
+        for name in [
+            "..",
+            "../outside",
+            "web/../../outside",
+            "web/..",
+            ".",
+            "web/./mail",
+        ] {
+            assert_invalid(name);
+        }
+    }
+
+    #[test]
+    fn rejects_absolute_paths() {
+        for name in ["/etc/passwd", "/web"] {
+            assert_invalid(name);
+        }
+    }
+
+    #[test]
+    fn rejects_empty_names_and_segments() {
+        for name in ["", "web//mail", "web/", "web/mail/"] {
+            assert_invalid(name);
+        }
+    }
+
+    #[test]
+    fn rejects_reserved_names() {
+        for name in [
+            ".git",
+            ".git/config",
+            "web/.git",
+            ".attachments",
+            ".attachments/web/mail",
+            ".rspass.toml",
+            ".mail.42-0.tmp",
+            "web/.mail.tmp",
+        ] {
+            assert_invalid(name);
+        }
+    }
+
+    #[test]
+    fn rejects_nul_bytes_and_backslashes() {
+        assert_invalid("web\0mail");
+        assert_invalid("web\\mail");
+        assert_invalid("..\\outside");
+    }
+
+    #[test]
+    fn segments_must_not_contain_separators() {
+        assert!(CredentialName::segment("mail").is_ok());
+        assert_eq!(
+            CredentialName::segment("web/mail").unwrap_err().kind,
+            ErrorKind::InvalidName
+        );
+    }
+}
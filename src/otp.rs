@@ -0,0 +1,380 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+
+use super::{Credential, Error, ErrorKind, Result};
+use crate::store::Store;
+
+pub(crate) const OTPAUTH_FIELD: &str = "otpauth";
+pub(crate) const OTPAUTH_SCHEME: &str = "otpauth://";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OtpKind {
+    Totp { period: u64 },
+    Hotp { counter: u64 },
+}
+
+#[derive(Debug, Clone)]
+struct OtpAuth {
+    kind: OtpKind,
+    algorithm: Algorithm,
+    digits: u32,
+    secret: Vec<u8>,
+}
+
+fn otp_error(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::OtpError, message)
+}
+
+fn decode_base32(value: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer: u64 = 0;
+    let mut bits = 0;
+
+    for c in value.chars().filter(|c| *c != '=' && !c.is_whitespace()) {
+        let digit = match c.to_ascii_uppercase() {
+            c @ 'A'..='Z' => c as u64 - 'A' as u64,
+            c @ '2'..='7' => c as u64 - '2' as u64 + 26,
+            _ => return None,
+        };
+
+        buffer = (buffer << 5) | digit;
+        bits += 5;
+
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    Some(bytes)
+}
+
+fn decode_percent(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut position = 0;
+
+    while position < bytes.len() {
+        let hex = bytes
+            .get(position + 1..position + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[position], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                position += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                position += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                position += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+impl OtpAuth {
+    fn parse(uri: &str) -> Result<Self> {
+        let rest = uri
+            .strip_prefix(OTPAUTH_SCHEME)
+            .ok_or_else(|| otp_error("otpauth uri must start with otpauth://"))?;
+
+        let (kind, rest) = rest
+            .split_once('/')
+            .ok_or_else(|| otp_error("otpauth uri is missing the otp type"))?;
+        let query = rest.split_once('?').map(|(_, query)| query).unwrap_or("");
+
+        let params: Vec<(String, String)> = query
+            .split('&')
+            .filter_map(|param| param.split_once('='))
+            .map(|(key, value)| (key.to_ascii_lowercase(), decode_percent(value)))
+            .collect();
+
+        let param = |key: &str| {
+            params
+                .iter()
+                .find(|(param, _)| param == key)
+                .map(|(_, value)| value.as_str())
+        };
+
+        let number = |key: &str, default: u64| match param(key) {
+            Some(value) => value.parse::<u64>().map_err(|err| {
+                otp_error(format!("invalid otpauth {} {:?}", key, value)).with_source(err)
+            }),
+            None => Ok(default),
+        };
+
+        let secret = param("secret")
+            .and_then(decode_base32)
+            .filter(|secret| !secret.is_empty())
+            .ok_or_else(|| otp_error("otpauth uri has a missing or invalid secret"))?;
+
+        let algorithm = match param("algorithm").map(|value| value.to_ascii_uppercase()) {
+            None => Algorithm::Sha1,
+            Some(value) if value == "SHA1" => Algorithm::Sha1,
+            Some(value) if value == "SHA256" => Algorithm::Sha256,
+            Some(value) if value == "SHA512" => Algorithm::Sha512,
+            Some(value) => return Err(otp_error(format!("unsupported algorithm {:?}", value))),
+        };
+
+        let digits = number("digits", 6)?;
+
+        if !(6..=10).contains(&digits) {
+            return Err(otp_error(format!(
+                "unsupported number of digits {}",
+                digits
+            )));
+        }
+
+        let kind = match kind.to_ascii_lowercase().as_str() {
+            "totp" => match number("period", 30)? {
+                0 => return Err(otp_error("otpauth period must be greater than zero")),
+                period => OtpKind::Totp { period },
+            },
+            "hotp" => OtpKind::Hotp {
+                counter: number("counter", 0)?,
+            },
+            other => return Err(otp_error(format!("unsupported otp type {:?}", other))),
+        };
+
+        Ok(OtpAuth {
+            kind,
+            algorithm,
+            digits: digits as u32,
+            secret,
+        })
+    }
+
+    fn sign(&self, counter: u64) -> Result<Vec<u8>> {
+        let message = counter.to_be_bytes();
+        let invalid_key = |_| otp_error("invalid otp secret");
+
+        Ok(match self.algorithm {
+            Algorithm::Sha1 => {
+                let mut mac = Hmac::<Sha1>::new_from_slice(&self.secret).map_err(invalid_key)?;
+                mac.update(&message);
+                mac.finalize().into_bytes().to_vec()
+            }
+            Algorithm::Sha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).map_err(invalid_key)?;
+                mac.update(&message);
+                mac.finalize().into_bytes().to_vec()
+            }
+            Algorithm::Sha512 => {
+                let mut mac = Hmac::<Sha512>::new_from_slice(&self.secret).map_err(invalid_key)?;
+                mac.update(&message);
+                mac.finalize().into_bytes().to_vec()
+            }
+        })
+    }
+
+    fn generate(&self, counter: u64) -> Result<String> {
+        let hash = self.sign(counter)?;
+        let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            hash[offset] & 0x7f,
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]);
+
+        let code = binary as u64 % 10u64.pow(self.digits);
+
+        Ok(format!("{:0width$}", code, width = self.digits as usize))
+    }
+}
+
+fn set_counter(uri: &str, counter: u64) -> String {
+    let (base, query) = uri.split_once('?').unwrap_or((uri, ""));
+
+    let mut params: Vec<String> = query
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .filter(|param| {
+            !param
+                .split('=')
+                .next()
+                .is_some_and(|key| key.eq_ignore_ascii_case("counter"))
+        })
+        .map(|param| param.to_owned())
+        .collect();
+
+    params.push(format!("counter={}", counter));
+
+    format!("{}?{}", base, params.join("&"))
+}
+
+fn otpauth_field(credential: &Credential, name: &str) -> Result<(String, String)> {
+    credential
+        .fields
+        .iter()
+        .find(|(key, value)| key == OTPAUTH_FIELD || value.starts_with(OTPAUTH_SCHEME))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("no otpauth field found for {:?}", name),
+            )
+        })
+}
+
+impl Store {
+    pub fn get_otp(
+        &self,
+        name: &str,
+        passphrase: &str,
+        at_time: Option<DateTime<Utc>>,
+    ) -> Result<String> {
+        let credential = self.get_credential(name, passphrase)?;
+        let (_, uri) = otpauth_field(&credential, name)?;
+
+        if !matches!(OtpAuth::parse(&uri)?.kind, OtpKind::Hotp { .. }) {
+            return self.generate_otp(name, credential, at_time);
+        }
+
+        // HOTP advances the counter saved in the credential, so it is read again under the
+        // lock to never hand out the same code twice.
+        let _lock = self.lock(&self.open()?)?;
+        let credential = self.get_credential(name, passphrase)?;
+
+        self.generate_otp(name, credential, at_time)
+    }
+
+    fn generate_otp(
+        &self,
+        name: &str,
+        mut credential: Credential,
+        at_time: Option<DateTime<Utc>>,
+    ) -> Result<String> {
+        let (key, uri) = otpauth_field(&credential, name)?;
+        let otp = OtpAuth::parse(&uri)?;
+
+        match otp.kind {
+            OtpKind::Totp { period } => {
+                let timestamp = at_time.unwrap_or_else(Utc::now).timestamp();
+                let timestamp = u64::try_from(timestamp).map_err(|err| {
+                    otp_error("totp time must not be before the unix epoch").with_source(err)
+                })?;
+
+                otp.generate(timestamp / period)
+            }
+            OtpKind::Hotp { counter } => {
+                let next = counter
+                    .checked_add(1)
+                    .ok_or_else(|| otp_error("hotp counter cannot be advanced any further"))?;
+                let code = otp.generate(counter)?;
+
+                credential.set(key, set_counter(&uri, next));
+                self.replace_credential(name, &credential)?;
+
+                Ok(code)
+            }
+        }
+    }
+}
+
+pub fn get_otp(name: &str, passphrase: &str, at_time: Option<DateTime<Utc>>) -> Result<String> {
+    Store::default().get_otp(name, passphrase, at_time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA1_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+    const SHA256_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA====";
+    const SHA512_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\
+                                 GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNA=";
+
+    fn totp(secret: &str, algorithm: &str, time: u64) -> String {
+        let uri = format!("otpauth://totp/Test?secret={secret}&algorithm={algorithm}&digits=8");
+        let otp = OtpAuth::parse(&uri).unwrap();
+
+        match otp.kind {
+            OtpKind::Totp { period } => otp.generate(time / period).unwrap(),
+            OtpKind::Hotp { .. } => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn generates_rfc_4226_hotp_values() {
+        let expected = [
+            "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583",
+            "399871", "520489",
+        ];
+        let otp = OtpAuth::parse(&format!("otpauth://hotp/Test?secret={SHA1_SECRET}")).unwrap();
+
+        assert_eq!(otp.kind, OtpKind::Hotp { counter: 0 });
+
+        for (counter, code) in expected.iter().enumerate() {
+            assert_eq!(otp.generate(counter as u64).unwrap(), *code);
+        }
+    }
+
+    #[test]
+    fn generates_rfc_6238_totp_values() {
+        let expected = [
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1111111111, "14050471", "67062674", "99943326"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (2000000000, "69279037", "90698825", "38618901"),
+            (20000000000, "65353130", "77737706", "47863826"),
+        ];
+
+        for (time, sha1, sha256, sha512) in expected {
+            assert_eq!(totp(SHA1_SECRET, "SHA1", time), sha1);
+            assert_eq!(totp(SHA256_SECRET, "SHA256", time), sha256);
+            assert_eq!(totp(SHA512_SECRET, "SHA512", time), sha512);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_otpauth_uris() {
+        assert!(OtpAuth::parse("https://totp/Test?secret=GEZA").is_err());
+        assert!(OtpAuth::parse("otpauth://totp/Test?secret=1").is_err());
+        assert!(OtpAuth::parse("otpauth://totp/Test?secret=GEZA&digits=5").is_err());
+        assert!(OtpAuth::parse("otpauth://totp/Test?secret=GEZA&period=0").is_err());
+        assert!(OtpAuth::parse("otpauth://totp/Test?secret=GEZA&algorithm=MD5").is_err());
+    }
+
+    #[test]
+    fn refuses_to_advance_the_last_hotp_counter() {
+        let credential = Credential::new("x").with_field(
+            OTPAUTH_FIELD,
+            format!(
+                "otpauth://hotp/Test?secret={SHA1_SECRET}&counter={}",
+                u64::MAX
+            ),
+        );
+        let store = Store::new("missing-store", "missing-config");
+
+        let err = store.generate_otp("test", credential, None).unwrap_err();
+
+        assert_eq!(err.kind, ErrorKind::OtpError);
+    }
+
+    #[test]
+    fn replaces_the_hotp_counter() {
+        assert_eq!(
+            set_counter("otpauth://hotp/Test?secret=GEZA&Counter=4&digits=6", 5),
+            "otpauth://hotp/Test?secret=GEZA&digits=6&counter=5"
+        );
+    }
+}
@@ -1,9 +1,12 @@
+use std::path::Path;
 use std::{fs::File, io::Read};
 
+use aes_gcm::{aead::Aead, AeadCore, Aes256Gcm, KeyInit, Nonce};
 use chrono::Utc;
 use pgp::{
     types::{SecretKeyRepr, SecretKeyTrait},
-    ArmorOptions, Deserializable, KeyType, SecretKeyParamsBuilder, SignedSecretKey,
+    ArmorOptions, Deserializable, KeyType, SecretKeyParamsBuilder, SignedPublicKey,
+    SignedSecretKey,
 };
 use rand::{rngs::OsRng, thread_rng};
 use rsa::{
@@ -19,6 +22,68 @@ pub struct Keys {
     pub rsa_pub_key: String,
 }
 
+fn crypto_error<E>(action: &str, err: E) -> Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    Error::new(ErrorKind::Crypto, format!("failed to {}. {}", action, err)).with_source(err)
+}
+
+fn unsupported_key() -> pgp::errors::Error {
+    pgp::errors::Error::Message("only RSA keys are supported".to_owned())
+}
+
+fn parse_rsa_pub_key(pub_key: &str) -> Result<RsaPublicKey> {
+    RsaPublicKey::from_pkcs1_pem(pub_key).map_err(|err| {
+        Error::new(
+            ErrorKind::Corrupt,
+            format!("invalid RSA public key. {}", err),
+        )
+        .with_source(err)
+    })
+}
+
+fn parse_secret_key(private_key: &str) -> Result<SignedSecretKey> {
+    SignedSecretKey::from_string(private_key)
+        .map(|(key, _)| key)
+        .map_err(|err| {
+            Error::new(ErrorKind::Corrupt, format!("invalid private key. {}", err)).with_source(err)
+        })
+}
+
+pub(crate) fn validate_pub_key(pub_key: &str) -> Result<()> {
+    SignedPublicKey::from_string(pub_key)
+        .map(|_| ())
+        .map_err(|err| {
+            Error::new(ErrorKind::Corrupt, format!("invalid public key. {}", err)).with_source(err)
+        })
+}
+
+pub(crate) fn validate_private_key(private_key: &str) -> Result<()> {
+    parse_secret_key(private_key).map(|_| ())
+}
+
+pub(crate) fn validate_rsa_pub_key(pub_key: &str) -> Result<()> {
+    parse_rsa_pub_key(pub_key).map(|_| ())
+}
+
+pub(crate) fn same_rsa_pub_key(first: &str, second: &str) -> Result<bool> {
+    Ok(parse_rsa_pub_key(first)? == parse_rsa_pub_key(second)?)
+}
+
+fn encrypt_key(pub_key: &RsaPublicKey, value: &[u8]) -> Result<Vec<u8>> {
+    pub_key
+        .encrypt(&mut thread_rng(), rsa::Pkcs1v15Encrypt, value)
+        .map_err(|err| match err {
+            err @ rsa::errors::Error::MessageTooLong => Error::new(
+                ErrorKind::EncryptationError,
+                "value is too large to encrypt with the RSA key",
+            )
+            .with_source(err),
+            err => crypto_error("encrypt data", err),
+        })
+}
+
 pub(crate) fn generate_key(name: &str, email: &str, password: &str) -> Result<Keys> {
     let params = SecretKeyParamsBuilder::default()
         .key_type(KeyType::Rsa(2048))
@@ -29,16 +94,24 @@ pub(crate) fn generate_key(name: &str, email: &str, password: &str) -> Result<Ke
         .can_encrypt(true)
         .created_at(Utc::now())
         .build()
-        .unwrap();
+        .map_err(|err| crypto_error("build key parameters", err))?;
 
-    let key = params.generate(OsRng).unwrap();
-    let secret_key = key.sign(OsRng, || password.to_owned()).unwrap();
+    let key = params
+        .generate(OsRng)
+        .map_err(|err| crypto_error("generate key", err))?;
+    let secret_key = key
+        .sign(OsRng, || password.to_owned())
+        .map_err(|err| crypto_error("sign private key", err))?;
 
-    let pub_key = secret_key
-        .public_key()
-        .sign(OsRng, &secret_key, || password.to_owned())
-        .unwrap();
+    export_keys(&secret_key, password)
+}
+
+/// Rebuilds the public keys of an existing private key, unlocking it with `password`.
+pub(crate) fn derive_keys(private_key: &str, password: &str) -> Result<Keys> {
+    export_keys(&parse_secret_key(private_key)?, password)
+}
 
+fn export_keys(secret_key: &SignedSecretKey, password: &str) -> Result<Keys> {
     let rsa_pub_key = secret_key
         .unlock(
             || password.to_owned(),
@@ -49,140 +122,250 @@ pub(crate) fn generate_key(name: &str, email: &str, password: &str) -> Result<Ke
 
                     Ok(pub_key)
                 }
-                _ => panic!("invalid private key data"),
+                _ => Err(unsupported_key()),
             },
         )
         .map_err(|err| match err {
             pgp::errors::Error::RSAError(err) => {
-                Error::new(ErrorKind::EncryptationError, err.to_string())
+                Error::new(ErrorKind::EncryptationError, err.to_string()).with_source(err)
             }
-            _ => panic!("unexpected error while encrypting message"),
+            err => Error::new(
+                ErrorKind::DecryptationError,
+                "failed to unlock the private key",
+            )
+            .with_source(err),
         })?;
 
+    let pub_key = secret_key
+        .public_key()
+        .sign(OsRng, secret_key, || password.to_owned())
+        .map_err(|err| crypto_error("sign public key", err))?;
+
     let rsa_pub_key = rsa_pub_key
         .to_pkcs1_pem(rsa::pkcs8::LineEnding::LF)
-        .unwrap();
+        .map_err(|err| crypto_error("encode RSA public key", err))?;
 
-    let armored_pub_key = pub_key.to_armored_string(ArmorOptions::default());
-    let armored_secret_key = secret_key.to_armored_string(ArmorOptions::default());
+    let armored_pub_key = pub_key
+        .to_armored_string(ArmorOptions::default())
+        .map_err(|err| crypto_error("armor public key", err))?;
+    let armored_secret_key = secret_key
+        .to_armored_string(ArmorOptions::default())
+        .map_err(|err| crypto_error("armor private key", err))?;
 
     Ok(Keys {
-        private_key: armored_secret_key.unwrap(),
-        pub_key: armored_pub_key.unwrap(),
+        private_key: armored_secret_key,
+        pub_key: armored_pub_key,
         rsa_pub_key,
     })
 }
 
 #[allow(dead_code)]
-pub(crate) fn recover_pub_key() -> Result<String> {
-    let config_dir = super::get_config_path();
+pub(crate) fn recover_pub_key(path: &Path) -> Result<String> {
     let mut pub_key = String::new();
 
-    File::open(config_dir.join("rspass.pub"))
+    File::open(path)
         .map_err(|err| match err.kind() {
             std::io::ErrorKind::NotFound => {
-                Error::new(ErrorKind::NotInitialized, "Public key not found")
+                Error::new(ErrorKind::NotInitialized, "Public key not found").with_source(err)
             }
             std::io::ErrorKind::InvalidData => {
-                Error::new(ErrorKind::BadConfig, "Invalid public key")
+                Error::new(ErrorKind::BadConfig, "Invalid public key").with_source(err)
             }
-            _ => panic!("Unexpected error when opening public key"),
+            _ => Error::new(
+                ErrorKind::Io,
+                format!("failed to open public key {:?}. {}", path, err),
+            )
+            .with_source(err),
         })?
         .read_to_string(&mut pub_key)
         .map_err(|err| match err.kind() {
             std::io::ErrorKind::InvalidData => {
-                Error::new(ErrorKind::BadConfig, "Invalid public key")
+                Error::new(ErrorKind::BadConfig, "Invalid public key").with_source(err)
             }
-            _ => panic!("Unexpected error when reading public key"),
+            _ => Error::new(
+                ErrorKind::Io,
+                format!("failed to read public key {:?}. {}", path, err),
+            )
+            .with_source(err),
         })?;
 
     Ok(pub_key)
 }
 
-pub(crate) fn recover_private_key() -> Result<String> {
-    let config_dir = super::get_config_path();
+pub(crate) fn recover_private_key(path: &Path) -> Result<String> {
     let mut private_key = String::new();
 
-    File::open(config_dir.join("rspass.key"))
+    File::open(path)
         .map_err(|err| match err.kind() {
             std::io::ErrorKind::NotFound => {
-                Error::new(ErrorKind::NotInitialized, "Private key not found")
+                Error::new(ErrorKind::NotInitialized, "Private key not found").with_source(err)
             }
             std::io::ErrorKind::InvalidData => {
-                Error::new(ErrorKind::BadConfig, "Invalid private key")
+                Error::new(ErrorKind::BadConfig, "Invalid private key").with_source(err)
             }
-            _ => panic!("Unexpected error when opening private key"),
+            _ => Error::new(
+                ErrorKind::Io,
+                format!("failed to open private key {:?}. {}", path, err),
+            )
+            .with_source(err),
         })?
         .read_to_string(&mut private_key)
         .map_err(|err| match err.kind() {
             std::io::ErrorKind::InvalidData => {
-                Error::new(ErrorKind::BadConfig, "Invalid private key")
+                Error::new(ErrorKind::BadConfig, "Invalid private key").with_source(err)
             }
-            _ => panic!("Unexpected error when reading private key"),
+            _ => Error::new(
+                ErrorKind::Io,
+                format!("failed to read private key {:?}. {}", path, err),
+            )
+            .with_source(err),
         })?;
 
     Ok(private_key)
 }
 
-pub(crate) fn recover_rsa_pub_key() -> Result<String> {
-    let config_dir = super::get_config_path();
-
+pub(crate) fn recover_rsa_pub_key(path: &Path) -> Result<String> {
     let mut rsa_key = String::new();
 
-    File::open(config_dir.join("rspass.pem"))
+    File::open(path)
         .map_err(|err| match err.kind() {
             std::io::ErrorKind::NotFound => {
-                Error::new(ErrorKind::NotInitialized, "Public RSA key not found")
+                Error::new(ErrorKind::NotInitialized, "Public RSA key not found").with_source(err)
             }
             std::io::ErrorKind::InvalidData => {
-                Error::new(ErrorKind::BadConfig, "Invalid RSA public key")
+                Error::new(ErrorKind::BadConfig, "Invalid RSA public key").with_source(err)
             }
-            _ => panic!("Unexpected error when opening public RSA key"),
+            _ => Error::new(
+                ErrorKind::Io,
+                format!("failed to open RSA public key {:?}. {}", path, err),
+            )
+            .with_source(err),
         })?
         .read_to_string(&mut rsa_key)
         .map_err(|err| match err.kind() {
             std::io::ErrorKind::InvalidData => {
-                Error::new(ErrorKind::BadConfig, "Invalid RSA public key")
+                Error::new(ErrorKind::BadConfig, "Invalid RSA public key").with_source(err)
             }
-            _ => panic!("Unexpected error when reading RSA public key"),
+            _ => Error::new(
+                ErrorKind::Io,
+                format!("failed to read RSA public key {:?}. {}", path, err),
+            )
+            .with_source(err),
         })?;
 
     Ok(rsa_key)
 }
 
-pub(crate) fn encrypt(value: String, pub_key: String) -> Result<Vec<u8>> {
-    let pub_key =
-        RsaPublicKey::from_pkcs1_pem(&pub_key).expect("value should be a valid public key");
+/// Credential files start with this header followed by the [`encrypt_data`] format. Files
+/// without it were written as a single RSA block, which limits them to the key size.
+const CREDENTIAL_HEADER: &[u8] = b"RSPASS\x00\x02";
 
-    let mut rng = thread_rng();
-
-    let encrypted_data = pub_key
-        .encrypt(&mut rng, rsa::Pkcs1v15Encrypt, value.as_bytes())
-        .unwrap();
+pub(crate) fn encrypt(value: String, pub_key: String) -> Result<Vec<u8>> {
+    let mut data = CREDENTIAL_HEADER.to_vec();
+    data.extend(encrypt_data(value.as_bytes(), pub_key)?);
 
-    Ok(encrypted_data)
+    Ok(data)
 }
 
 pub(crate) fn decrypt(value: Vec<u8>, passprase: &str, private_key: String) -> Result<String> {
-    let (private_key, _) =
-        SignedSecretKey::from_string(&private_key).expect("value should be a valid private key");
+    let private_key = parse_secret_key(&private_key)?;
 
     let decrypted_data = private_key
         .unlock(
             || passprase.to_owned(),
             |key| match key {
-                SecretKeyRepr::RSA(key) => key
-                    .decrypt(rsa::Pkcs1v15Encrypt, &value)
-                    .map_err(|err| pgp::errors::Error::RSAError(err)),
-                _ => panic!("unexpected params type {key:?}"),
+                SecretKeyRepr::RSA(key) => match value.strip_prefix(CREDENTIAL_HEADER) {
+                    Some(data) => open_data(key, data),
+                    None => key
+                        .decrypt(rsa::Pkcs1v15Encrypt, &value)
+                        .map_err(pgp::errors::Error::RSAError),
+                },
+                _ => Err(unsupported_key()),
             },
         )
-        .map_err(|_err| {
+        .map_err(|err| {
             Error::new(
                 ErrorKind::DecryptationError,
                 "failed to decrypt data".to_owned(),
             )
+            .with_source(err)
         })?;
 
-    Ok(String::from_utf8(decrypted_data).unwrap())
+    String::from_utf8(decrypted_data).map_err(|err| {
+        Error::new(
+            ErrorKind::Corrupt,
+            "decrypted credential is not valid UTF-8",
+        )
+        .with_source(err)
+    })
+}
+
+const NONCE_SIZE: usize = 12;
+
+pub(crate) fn encrypt_data(value: &[u8], pub_key: String) -> Result<Vec<u8>> {
+    let pub_key = parse_rsa_pub_key(&pub_key)?;
+
+    let mut rng = thread_rng();
+
+    let key = Aes256Gcm::generate_key(&mut rng);
+    let nonce = Aes256Gcm::generate_nonce(&mut rng);
+
+    let wrapped_key = encrypt_key(&pub_key, key.as_slice())?;
+
+    let encrypted_data = Aes256Gcm::new(&key)
+        .encrypt(&nonce, value)
+        .map_err(|_| Error::new(ErrorKind::EncryptationError, "failed to encrypt data"))?;
+
+    let mut data = Vec::with_capacity(2 + wrapped_key.len() + NONCE_SIZE + encrypted_data.len());
+    data.extend_from_slice(&(wrapped_key.len() as u16).to_be_bytes());
+    data.extend_from_slice(&wrapped_key);
+    data.extend_from_slice(nonce.as_slice());
+    data.extend_from_slice(&encrypted_data);
+
+    Ok(data)
+}
+
+fn open_data(key: &RsaPrivateKey, value: &[u8]) -> pgp::errors::Result<Vec<u8>> {
+    let invalid_data = || pgp::errors::Error::RSAError(rsa::errors::Error::Decryption);
+
+    let (key_size, rest) = value.split_at_checked(2).ok_or_else(invalid_data)?;
+    let key_size = u16::from_be_bytes([key_size[0], key_size[1]]) as usize;
+    let (wrapped_key, rest) = rest.split_at_checked(key_size).ok_or_else(invalid_data)?;
+    let (nonce, encrypted_data) = rest.split_at_checked(NONCE_SIZE).ok_or_else(invalid_data)?;
+
+    let session_key = key
+        .decrypt(rsa::Pkcs1v15Encrypt, wrapped_key)
+        .map_err(pgp::errors::Error::RSAError)?;
+
+    let cipher = Aes256Gcm::new_from_slice(&session_key).map_err(|_| invalid_data())?;
+
+    cipher
+        .decrypt(Nonce::from_slice(nonce), encrypted_data)
+        .map_err(|_| invalid_data())
+}
+
+pub(crate) fn decrypt_data(
+    values: Vec<Vec<u8>>,
+    passprase: &str,
+    private_key: String,
+) -> Result<Vec<Vec<u8>>> {
+    let private_key = parse_secret_key(&private_key)?;
+
+    private_key
+        .unlock(
+            || passprase.to_owned(),
+            |key| match key {
+                SecretKeyRepr::RSA(key) => {
+                    values.iter().map(|value| open_data(key, value)).collect()
+                }
+                _ => Err(unsupported_key()),
+            },
+        )
+        .map_err(|err| {
+            Error::new(
+                ErrorKind::DecryptationError,
+                "failed to decrypt data".to_owned(),
+            )
+            .with_source(err)
+        })
 }
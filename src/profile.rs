@@ -0,0 +1,244 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use toml::{Table, Value};
+
+use super::{read_credential, CredentialName, Error, ErrorKind, Result, Transaction};
+use crate::attachment::attachments_path;
+use crate::config::{read_config_file, StoreConfig, CONFIG_FILE};
+use crate::pgp::decrypt_data;
+use crate::store::Store;
+use crate::transfer::collect_files;
+
+const PROFILES_DIR: &str = "profiles";
+const PROFILE_STORE_DIR: &str = "store";
+
+fn profile_name(name: &str) -> Result<CredentialName> {
+    CredentialName::segment(name).map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidName,
+            format!("invalid profile name {:?}", name),
+        )
+    })
+}
+
+fn map_io_error(err: io::Error, action: &str) -> Error {
+    match err.kind() {
+        io::ErrorKind::PermissionDenied => Error::new(
+            ErrorKind::PermissionDenied,
+            format!("You dont have permission to {} profiles", action),
+        )
+        .with_source(err),
+        _ => Error::new(
+            ErrorKind::Io,
+            format!("failed to {} profiles. {}", action, err),
+        )
+        .with_source(err),
+    }
+}
+
+fn attachment_files(repo_path: &Path, name: &str) -> Result<Vec<String>> {
+    let attachments = attachments_path(name);
+
+    if repo_path.join(&attachments).is_dir() {
+        collect_files(repo_path, &attachments)
+    } else {
+        Ok(Vec::new())
+    }
+}
+
+impl Store {
+    fn profiles_path(&self) -> Result<PathBuf> {
+        Ok(self.config_path()?.join(PROFILES_DIR))
+    }
+
+    /// Lists the profiles kept in the `profiles` folder of this store's config directory.
+    pub fn list_profiles(&self) -> Result<Vec<String>> {
+        let entries = match fs::read_dir(self.profiles_path()?) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(map_io_error(err, "list")),
+        };
+
+        let mut profiles: Vec<String> = entries
+            .flatten()
+            .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| profile_name(name).is_ok())
+            .collect();
+
+        profiles.sort();
+
+        Ok(profiles)
+    }
+
+    /// Opens the profile `name`, a store with its own config directory, keys and repository.
+    ///
+    /// The repository is read from `store.path` in the profile's `config.toml`, relative to the
+    /// profile directory, and defaults to its `store` folder.
+    pub fn open_profile(&self, name: &str) -> Result<Store> {
+        let name = profile_name(name)?;
+        let profile_path = name.path_in(&self.profiles_path()?);
+
+        if !profile_path.is_dir() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("no profile named {:?}", name.as_str()),
+            ));
+        }
+
+        let config = match read_config_file(&profile_path.join(CONFIG_FILE))? {
+            Some(contents) => StoreConfig::parse(&contents)?,
+            None => StoreConfig::default(),
+        };
+        let repo_path = profile_path.join(
+            config
+                .path
+                .as_deref()
+                .unwrap_or(Path::new(PROFILE_STORE_DIR)),
+        );
+
+        Ok(Store::new(repo_path, profile_path).with_lock_timeout(self.lock_timeout()))
+    }
+
+    /// Creates the profile `name` and initializes its repository, either at `repo_path` or in
+    /// the `store` folder of the profile. Keys are not created, use
+    /// [`Store::generate_keys`] on the returned store.
+    pub fn create_profile(&self, name: &str, repo_path: Option<&Path>) -> Result<Store> {
+        let name = profile_name(name)?;
+        let profiles_path = self.profiles_path()?;
+        let profile_path = name.path_in(&profiles_path);
+
+        fs::create_dir_all(&profiles_path).map_err(|err| map_io_error(err, "create"))?;
+
+        fs::create_dir(&profile_path).map_err(|err| match err.kind() {
+            io::ErrorKind::AlreadyExists => Error::new(
+                ErrorKind::AlreadyExists,
+                format!("A profile already exists with the name {:?}", name.as_str()),
+            )
+            .with_source(err),
+            _ => map_io_error(err, "create"),
+        })?;
+
+        if let Some(repo_path) = repo_path {
+            let path = repo_path.to_str().ok_or_else(|| {
+                Error::new(
+                    ErrorKind::BadConfig,
+                    format!("profile repository {:?} is not valid UTF-8", repo_path),
+                )
+            })?;
+            let mut store = Table::new();
+            store.insert("path".to_owned(), Value::String(path.to_owned()));
+            let mut config = Table::new();
+            config.insert("store".to_owned(), Value::Table(store));
+
+            fs::write(profile_path.join(CONFIG_FILE), config.to_string())
+                .map_err(|err| map_io_error(err, "create"))?;
+        }
+
+        let profile = self.open_profile(name.as_str())?;
+        profile.initialize_repository()?;
+
+        Ok(profile)
+    }
+
+    /// Copies a credential, or a folder of them, into another store such as a profile.
+    ///
+    /// Each credential and its attachments are decrypted with this store's key and encrypted
+    /// again for `target`, then written there in a single commit.
+    pub fn copy_credential_to(
+        &self,
+        target: &Store,
+        name: &str,
+        destination: &str,
+        password: &str,
+        force: bool,
+    ) -> Result<()> {
+        let name = CredentialName::new(name)?;
+        let destination = CredentialName::new(destination)?;
+        let repo_path = self.repo_path();
+        let mut transaction = Transaction::new();
+
+        for from in collect_files(repo_path, name.as_str())? {
+            let to = format!("{}{}", destination, &from[name.as_str().len()..]);
+            let to_path = target.repo_path().join(&to);
+            let credential = read_credential(self, &repo_path.join(&from), password)?;
+
+            if to_path.is_dir() || (to_path.exists() && !force) {
+                return Err(Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("A credential already exists with the name {:?}", to),
+                ));
+            }
+
+            if to_path.exists() {
+                transaction.replace(&to, &credential)?;
+            } else {
+                transaction.insert(&to, &credential)?;
+            }
+
+            let attachments = attachment_files(repo_path, &from)?;
+
+            if attachments.is_empty() {
+                continue;
+            }
+
+            let buffers = attachments
+                .iter()
+                .map(|path| {
+                    fs::read(repo_path.join(path)).map_err(|err| match err.kind() {
+                        io::ErrorKind::PermissionDenied => Error::new(
+                            ErrorKind::PermissionDenied,
+                            "You dont have permission to read this attachment",
+                        )
+                        .with_source(err),
+                        _ => Error::new(
+                            ErrorKind::Io,
+                            format!("failed to read attachment {:?}. {}", path, err),
+                        )
+                        .with_source(err),
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let data = decrypt_data(buffers, password, self.private_key()?)?;
+
+            for (path, data) in attachments.iter().zip(data) {
+                let attachment = path.rsplit('/').next().unwrap_or(path);
+                transaction.attach(&to, attachment, data)?;
+            }
+        }
+
+        transaction.commit_to(target, &format!("copy {} to {}", name, destination))
+    }
+}
+
+pub fn list_profiles() -> Result<Vec<String>> {
+    Store::default().list_profiles()
+}
+
+pub fn open_profile(name: &str) -> Result<Store> {
+    Store::default().open_profile(name)
+}
+
+pub fn create_profile(name: &str, repo_path: Option<&Path>) -> Result<Store> {
+    Store::default().create_profile(name, repo_path)
+}
+
+pub fn copy_credential_to_profile(
+    profile: &str,
+    name: &str,
+    destination: &str,
+    password: &str,
+    force: bool,
+) -> Result<()> {
+    let store = Store::default();
+
+    store.copy_credential_to(
+        &store.open_profile(profile)?,
+        name,
+        destination,
+        password,
+        force,
+    )
+}
@@ -0,0 +1,645 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use git2::{Index, ObjectType, Oid, ReferenceType, Repository, ResetType, Sort};
+
+use super::{CredentialName, Error, ErrorKind, Result};
+use crate::attachment::attachments_path;
+use crate::history::history_error;
+use crate::search_index::index_remove;
+use crate::store::Store;
+use crate::transfer::{collect_files, remove_empty_parents};
+
+#[derive(Debug, Clone)]
+pub struct RewriteReport {
+    pub branch: String,
+    pub old_head: String,
+    pub new_head: String,
+    pub rewritten_commits: usize,
+    pub dropped_commits: usize,
+    pub force_push_required: bool,
+    /// Other local branches and tags that were moved to the rewritten history.
+    pub rewritten_refs: Vec<String>,
+    /// Refs that still reach the removed data. Remote-tracking branches stay stale until they
+    /// are force-pushed.
+    pub stale_refs: Vec<String>,
+}
+
+/// A path to remove from history. A path the credential was moved away from is removed from
+/// every commit that does not descend from the move, including branches that split off earlier.
+struct PurgedPath {
+    path: String,
+    moved_in: Option<Oid>,
+}
+
+impl PurgedPath {
+    fn matches(&self, repo: &Repository, commit: Oid, path: &str) -> Result<bool> {
+        let in_path = path == self.path
+            || path
+                .strip_prefix(self.path.as_str())
+                .is_some_and(|rest| rest.starts_with('/'));
+
+        Ok(in_path
+            && match self.moved_in {
+                Some(moved_in) => {
+                    commit != moved_in
+                        && !repo
+                            .graph_descendant_of(commit, moved_in)
+                            .map_err(history_error)?
+                }
+                None => true,
+            })
+    }
+}
+
+/// A branch or tag outside the current branch, with the commit it points to.
+struct OtherRef {
+    name: String,
+    commit: Oid,
+    annotated_tag: Option<Oid>,
+}
+
+fn other_refs(repo: &Repository, branch: &str) -> Result<Vec<OtherRef>> {
+    let mut refs = Vec::new();
+
+    for reference in repo.references().map_err(history_error)? {
+        let reference = reference.map_err(history_error)?;
+
+        let (Some(name), Some(target)) = (reference.name(), reference.target()) else {
+            continue;
+        };
+
+        if name == branch || reference.kind() != Some(ReferenceType::Direct) {
+            continue;
+        }
+
+        if !["refs/heads/", "refs/tags/", "refs/remotes/"]
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+        {
+            continue;
+        }
+
+        let Ok(commit) = reference.peel_to_commit() else {
+            continue;
+        };
+        let annotated_tag = repo
+            .find_object(target, None)
+            .ok()
+            .filter(|object| object.kind() == Some(ObjectType::Tag))
+            .map(|object| object.id());
+
+        refs.push(OtherRef {
+            name: name.to_owned(),
+            commit: commit.id(),
+            annotated_tag,
+        });
+    }
+
+    Ok(refs)
+}
+
+/// Points the local branches and tags in `refs` to their rewritten commits, and returns the
+/// updated refs along with the remote-tracking ones that still point to the old history.
+fn update_other_refs(
+    repo: &Repository,
+    refs: Vec<OtherRef>,
+    rewritten: &HashMap<Oid, Option<Oid>>,
+    message: &str,
+) -> Result<(Vec<String>, Vec<String>)> {
+    let mut rewritten_refs = Vec::new();
+    let mut stale_refs = Vec::new();
+
+    for reference in refs {
+        let new_commit = rewritten.get(&reference.commit).copied().flatten();
+
+        if new_commit == Some(reference.commit) {
+            continue;
+        }
+
+        if reference.name.starts_with("refs/remotes/") {
+            stale_refs.push(reference.name);
+            continue;
+        }
+
+        match (new_commit, reference.annotated_tag) {
+            (Some(new_commit), Some(tag_id)) => {
+                let tag = repo.find_tag(tag_id).map_err(history_error)?;
+                let target = repo.find_object(new_commit, None).map_err(history_error)?;
+                let tag_name = reference.name.trim_start_matches("refs/tags/");
+
+                match tag.tagger() {
+                    Some(tagger) => repo.tag(
+                        tag_name,
+                        &target,
+                        &tagger,
+                        tag.message().unwrap_or_default(),
+                        true,
+                    ),
+                    None => repo.tag_lightweight(tag_name, &target, true),
+                }
+                .map_err(history_error)?;
+            }
+            (Some(new_commit), None) => {
+                repo.reference(&reference.name, new_commit, true, message)
+                    .map_err(history_error)?;
+            }
+            (None, _) => {
+                repo.find_reference(&reference.name)
+                    .and_then(|mut reference| reference.delete())
+                    .map_err(history_error)?;
+            }
+        }
+
+        let _ = repo.reflog_delete(&reference.name);
+        rewritten_refs.push(reference.name);
+    }
+
+    Ok((rewritten_refs, stale_refs))
+}
+
+fn remove_from_tree(repo_path: &Path, name: &str) -> Result<()> {
+    let path = repo_path.join(name);
+
+    if path.is_dir() {
+        fs::remove_dir_all(&path)
+    } else {
+        fs::remove_file(&path)
+    }
+    .or_else(|err| match err.kind() {
+        io::ErrorKind::NotFound => Ok(()),
+        _ => Err(err),
+    })
+    .map_err(|err| match err.kind() {
+        io::ErrorKind::PermissionDenied => Error::new(
+            ErrorKind::PermissionDenied,
+            "You dont have permission to remove this credential",
+        )
+        .with_source(err),
+        _ => Error::new(
+            ErrorKind::Io,
+            format!("failed to remove credential. {}", err),
+        )
+        .with_source(err),
+    })?;
+
+    remove_empty_parents(repo_path, name);
+
+    Ok(())
+}
+
+fn current_branch(repo: &Repository) -> Result<(String, Oid)> {
+    let head = repo.head().map_err(history_error)?;
+    let branch = head
+        .name()
+        .ok_or_else(|| Error::new(ErrorKind::HistoryError, "HEAD is not a valid reference"))?
+        .to_owned();
+    let oid = head
+        .target()
+        .ok_or_else(|| Error::new(ErrorKind::HistoryError, "HEAD does not point to a commit"))?;
+
+    Ok((branch, oid))
+}
+
+fn replace_branch(
+    repo: &Repository,
+    branch: &str,
+    old_head: Oid,
+    new_head: Oid,
+    message: &str,
+) -> Result<bool> {
+    repo.reference(branch, new_head, true, message)
+        .map_err(history_error)?;
+
+    let target = repo.find_object(new_head, None).map_err(history_error)?;
+    repo.reset(&target, ResetType::Mixed, None)
+        .map_err(history_error)?;
+
+    let _ = repo.reflog_delete(branch);
+    let _ = repo.reflog_delete("HEAD");
+
+    let has_remotes = repo
+        .remotes()
+        .map(|remotes| !remotes.is_empty())
+        .unwrap_or(false);
+
+    Ok(has_remotes && old_head != new_head)
+}
+
+impl Store {
+    /// Returns the paths of `name` and its attachments to remove from history, including the
+    /// paths the credential, or the credentials of a folder, were moved from.
+    fn purged_paths(&self, name: &CredentialName) -> Result<Vec<PurgedPath>> {
+        let files = match collect_files(self.repo_path(), name.as_str()) {
+            Ok(files) => files,
+            Err(err) if err.kind == ErrorKind::NotFound => vec![name.as_str().to_owned()],
+            Err(err) => return Err(err),
+        };
+
+        let mut paths = vec![PurgedPath {
+            path: name.as_str().to_owned(),
+            moved_in: None,
+        }];
+
+        for file in files {
+            paths.extend(
+                self.previous_paths(&file)?
+                    .into_iter()
+                    .map(|(path, moved_in)| PurgedPath {
+                        path,
+                        moved_in: Some(moved_in),
+                    }),
+            );
+        }
+
+        let attachments = paths
+            .iter()
+            .map(|path| PurgedPath {
+                path: attachments_path(&path.path),
+                moved_in: path.moved_in,
+            })
+            .collect::<Vec<_>>();
+        paths.extend(attachments);
+
+        Ok(paths)
+    }
+
+    /// Removes a credential and its attachments from every commit of the current branch, the
+    /// other local branches and the tags, following the credential through earlier moves.
+    ///
+    /// Remote-tracking branches are left untouched and listed in
+    /// [`RewriteReport::stale_refs`]. The reflogs of rewritten refs are deleted, but the old
+    /// objects stay in `.git/objects` until they are pruned, e.g. with `git gc --prune=now`
+    /// once every clone has been updated.
+    pub fn purge_credential(&self, name: &str) -> Result<RewriteReport> {
+        let name = CredentialName::new(name)?;
+        let repo_path = self.repo_path();
+        let repo = self.open()?;
+        let _lock = self.lock(&repo)?;
+        let (branch, old_head) = current_branch(&repo)?;
+        let purged_paths = self.purged_paths(&name)?;
+        let refs = other_refs(&repo, &branch)?;
+
+        let mut revwalk = repo.revwalk().map_err(history_error)?;
+        revwalk
+            .set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)
+            .map_err(history_error)?;
+        revwalk.push(old_head).map_err(history_error)?;
+
+        for reference in &refs {
+            revwalk.push(reference.commit).map_err(history_error)?;
+        }
+
+        let mut index = Index::new().map_err(history_error)?;
+        let mut rewritten: HashMap<Oid, Option<Oid>> = HashMap::new();
+        let mut rewritten_commits = 0;
+        let mut dropped_commits = 0;
+
+        for oid in revwalk {
+            let oid = oid.map_err(history_error)?;
+            let commit = repo.find_commit(oid).map_err(history_error)?;
+            let tree = commit.tree().map_err(history_error)?;
+
+            index.read_tree(&tree).map_err(history_error)?;
+
+            let mut removals = Vec::new();
+
+            for entry in index.iter() {
+                let path = String::from_utf8_lossy(&entry.path).into_owned();
+
+                for purged in &purged_paths {
+                    if purged.matches(&repo, oid, &path)? {
+                        removals.push(path);
+                        break;
+                    }
+                }
+            }
+
+            for path in &removals {
+                index.remove_path(Path::new(path)).map_err(history_error)?;
+            }
+
+            let tree_id = index.write_tree_to(&repo).map_err(history_error)?;
+
+            let parents = commit
+                .parent_ids()
+                .filter_map(|parent| rewritten.get(&parent).copied().flatten())
+                .map(|parent| repo.find_commit(parent))
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(history_error)?;
+
+            let original_parent_tree = commit.parent(0).ok().map(|parent| parent.tree_id());
+            let new_parent_tree = parents.first().map(|parent| parent.tree_id());
+
+            let became_empty = commit.parent_count() <= 1
+                && original_parent_tree != Some(tree.id())
+                && match new_parent_tree {
+                    Some(parent_tree) => parent_tree == tree_id,
+                    None => index.is_empty(),
+                };
+
+            if became_empty {
+                rewritten.insert(oid, parents.first().map(|parent| parent.id()));
+                dropped_commits += 1;
+                continue;
+            }
+
+            if tree_id == tree.id() && parents.iter().map(|p| p.id()).eq(commit.parent_ids()) {
+                rewritten.insert(oid, Some(oid));
+                continue;
+            }
+
+            let tree = repo.find_tree(tree_id).map_err(history_error)?;
+            let new_oid = repo
+                .commit(
+                    None,
+                    &commit.author(),
+                    &commit.committer(),
+                    commit.message().unwrap_or_default(),
+                    &tree,
+                    parents.iter().collect::<Vec<_>>().as_slice(),
+                )
+                .map_err(history_error)?;
+
+            rewritten.insert(oid, Some(new_oid));
+            rewritten_commits += 1;
+        }
+
+        let new_head = rewritten.get(&old_head).copied().flatten().ok_or_else(|| {
+            Error::new(
+                ErrorKind::HistoryError,
+                format!(
+                    "purging {:?} would leave the repository empty",
+                    name.as_str()
+                ),
+            )
+        })?;
+
+        remove_from_tree(repo_path, name.as_str())?;
+        remove_from_tree(repo_path, &attachments_path(name.as_str()))?;
+
+        let message = format!("purge {:?}", name.as_str());
+        let (rewritten_refs, stale_refs) = update_other_refs(&repo, refs, &rewritten, &message)?;
+        let force_push_required =
+            replace_branch(&repo, &branch, old_head, new_head, &message)? || !stale_refs.is_empty();
+
+        index_remove(self, name.as_str());
+
+        Ok(RewriteReport {
+            branch,
+            old_head: old_head.to_string(),
+            new_head: new_head.to_string(),
+            rewritten_commits,
+            dropped_commits,
+            force_push_required,
+            rewritten_refs,
+            stale_refs,
+        })
+    }
+
+    /// Replaces the history of the current branch with a single commit of its current tree.
+    ///
+    /// Only the current branch is rewritten. Other local branches, tags and remote-tracking
+    /// branches still reach the old history and are listed in [`RewriteReport::stale_refs`].
+    pub fn squash_history(&self, message: &str) -> Result<RewriteReport> {
+        let repo = self.open()?;
+        let _lock = self.lock(&repo)?;
+        let (branch, old_head) = current_branch(&repo)?;
+        let stale_refs = other_refs(&repo, &branch)?
+            .into_iter()
+            .map(|reference| reference.name)
+            .collect();
+
+        let head_commit = repo.find_commit(old_head).map_err(history_error)?;
+        let tree = head_commit.tree().map_err(history_error)?;
+        let signature = self.signature()?;
+
+        let mut revwalk = repo.revwalk().map_err(history_error)?;
+        revwalk.push(old_head).map_err(history_error)?;
+        let dropped_commits = revwalk.count().saturating_sub(1);
+
+        let new_head = repo
+            .commit(None, &signature, &signature, message, &tree, &[])
+            .map_err(history_error)?;
+
+        let force_push_required = replace_branch(&repo, &branch, old_head, new_head, message)?;
+
+        Ok(RewriteReport {
+            branch,
+            old_head: old_head.to_string(),
+            new_head: new_head.to_string(),
+            rewritten_commits: 1,
+            dropped_commits,
+            force_push_required,
+            rewritten_refs: Vec::new(),
+            stale_refs,
+        })
+    }
+}
+
+pub fn purge_credential(name: &str) -> Result<RewriteReport> {
+    Store::default().purge_credential(name)
+}
+
+pub fn squash_history(message: &str) -> Result<RewriteReport> {
+    Store::default().squash_history(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use git2::{Commit, Signature};
+
+    fn test_store(name: &str) -> Store {
+        let dir =
+            std::env::temp_dir().join(format!("rspass-rewrite-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let store = Store::new(dir.join("repo"), dir.join("config"));
+        store.initialize_repository().unwrap();
+
+        store
+    }
+
+    /// Commits `files` as the whole tree of a new commit on `reference`.
+    fn commit_files<'r>(
+        repo: &'r Repository,
+        reference: &str,
+        parent: Option<&Commit>,
+        files: &[(&str, &[u8])],
+    ) -> Commit<'r> {
+        let mut index = Index::new().unwrap();
+
+        for (path, data) in files {
+            let entry = git2::IndexEntry {
+                ctime: git2::IndexTime::new(0, 0),
+                mtime: git2::IndexTime::new(0, 0),
+                dev: 0,
+                ino: 0,
+                mode: 0o100644,
+                uid: 0,
+                gid: 0,
+                file_size: data.len() as u32,
+                id: repo.blob(data).unwrap(),
+                flags: 0,
+                flags_extended: 0,
+                path: path.as_bytes().to_vec(),
+            };
+            index.add(&entry).unwrap();
+        }
+
+        let tree = repo.find_tree(index.write_tree_to(repo).unwrap()).unwrap();
+        let signature = Signature::now("rspass", "rspass@rspass").unwrap();
+        let parents = parent.into_iter().collect::<Vec<_>>();
+        let oid = repo
+            .commit(
+                Some(reference),
+                &signature,
+                &signature,
+                "test",
+                &tree,
+                &parents,
+            )
+            .unwrap();
+
+        repo.find_commit(oid).unwrap()
+    }
+
+    fn paths_in_history(repo: &Repository, reference: &str) -> Vec<String> {
+        let mut revwalk = repo.revwalk().unwrap();
+        revwalk.push_ref(reference).unwrap();
+        let mut paths = Vec::new();
+
+        for oid in revwalk {
+            let tree = repo.find_commit(oid.unwrap()).unwrap().tree().unwrap();
+            tree.walk(git2::TreeWalkMode::PreOrder, |root, entry| {
+                if entry.kind() == Some(ObjectType::Blob) {
+                    paths.push(format!("{}{}", root, entry.name().unwrap()));
+                }
+                git2::TreeWalkResult::Ok
+            })
+            .unwrap();
+        }
+
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+
+    #[test]
+    fn purges_earlier_paths_from_branches_that_split_before_the_move() {
+        let store = test_store("moved");
+        let repo = store.open().unwrap();
+        let added = commit_files(
+            &repo,
+            "HEAD",
+            None,
+            &[("secret", b"ciphertext"), ("other", b"x")],
+        );
+        commit_files(
+            &repo,
+            "refs/heads/side",
+            Some(&added),
+            &[("secret", b"ciphertext"), ("other", b"x"), ("side", b"y")],
+        );
+        commit_files(
+            &repo,
+            "HEAD",
+            Some(&added),
+            &[("moved", b"ciphertext"), ("other", b"x")],
+        );
+
+        let report = store.purge_credential("moved").unwrap();
+
+        assert_eq!(report.rewritten_refs, ["refs/heads/side"]);
+        assert_eq!(paths_in_history(&repo, "HEAD"), ["other"]);
+        assert_eq!(
+            paths_in_history(&repo, "refs/heads/side"),
+            ["other", "side"]
+        );
+    }
+
+    #[test]
+    fn purges_attachments_under_earlier_names_on_every_branch() {
+        let store = test_store("attachments");
+        let repo = store.open().unwrap();
+        let added = commit_files(
+            &repo,
+            "HEAD",
+            None,
+            &[
+                ("secret", b"ciphertext"),
+                (".attachments/secret/key", b"data"),
+            ],
+        );
+        commit_files(
+            &repo,
+            "refs/heads/side",
+            Some(&added),
+            &[
+                ("secret", b"ciphertext"),
+                (".attachments/secret/key", b"data"),
+                (".attachments/secret/other", b"more"),
+                ("side", b"y"),
+            ],
+        );
+        commit_files(
+            &repo,
+            "HEAD",
+            Some(&added),
+            &[
+                ("moved", b"ciphertext"),
+                (".attachments/moved/key", b"data"),
+                ("other", b"x"),
+            ],
+        );
+
+        store.purge_credential("moved").unwrap();
+
+        assert_eq!(paths_in_history(&repo, "HEAD"), ["other"]);
+        assert_eq!(paths_in_history(&repo, "refs/heads/side"), ["side"]);
+    }
+
+    #[test]
+    fn squashing_reports_the_refs_it_leaves_behind() {
+        let store = test_store("squash");
+        let repo = store.open().unwrap();
+        let first = commit_files(&repo, "HEAD", None, &[("secret", b"old")]);
+        repo.reference("refs/heads/side", first.id(), false, "test")
+            .unwrap();
+        repo.tag_lightweight("v1", first.as_object(), false)
+            .unwrap();
+        commit_files(&repo, "HEAD", Some(&first), &[("secret", b"new")]);
+
+        let report = store.squash_history("squash").unwrap();
+
+        assert_eq!(report.dropped_commits, 1);
+        assert_eq!(report.stale_refs, ["refs/heads/side", "refs/tags/v1"]);
+        assert!(report.rewritten_refs.is_empty());
+    }
+
+    #[test]
+    fn keeps_a_reused_name_created_after_the_move() {
+        let store = test_store("reused");
+        let repo = store.open().unwrap();
+        let added = commit_files(&repo, "HEAD", None, &[("secret", b"old"), ("other", b"x")]);
+        let moved = commit_files(
+            &repo,
+            "HEAD",
+            Some(&added),
+            &[("moved", b"old"), ("other", b"x")],
+        );
+        commit_files(
+            &repo,
+            "HEAD",
+            Some(&moved),
+            &[("moved", b"old"), ("other", b"x"), ("secret", b"new")],
+        );
+
+        store.purge_credential("moved").unwrap();
+
+        assert_eq!(paths_in_history(&repo, "HEAD"), ["other", "secret"]);
+    }
+}
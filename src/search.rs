@@ -0,0 +1,105 @@
+use super::Result;
+use crate::search_index::{indexed_fields, load_index, searchable_fields};
+use crate::store::Store;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchMatch {
+    pub name: String,
+    pub score: usize,
+    pub name_matched: bool,
+    pub fields: Vec<(String, String)>,
+}
+
+fn fuzzy_score(query: &str, candidate: &str) -> Option<usize> {
+    let candidate: Vec<char> = candidate.to_lowercase().chars().collect();
+    let mut position = 0;
+    let mut score = 0;
+    let mut previous: Option<usize> = None;
+
+    for c in query.to_lowercase().chars() {
+        let found = candidate[position..].iter().position(|other| *other == c)? + position;
+
+        score += 1;
+
+        if previous.is_some_and(|previous| previous + 1 == found) {
+            score += 2;
+        }
+
+        if found == 0 || candidate[found - 1] == '/' {
+            score += 3;
+        }
+
+        previous = Some(found);
+        position = found + 1;
+    }
+
+    Some(score)
+}
+
+impl Store {
+    pub fn search(&self, query: &str, passphrase: Option<&str>) -> Result<Vec<SearchMatch>> {
+        let needle = query.to_lowercase();
+        let index = match passphrase {
+            Some(passphrase) => load_index(self, passphrase)?,
+            None => None,
+        };
+        let mut matches = Vec::new();
+        let mut unreadable = Vec::new();
+        let names = self.list_credentials(None)?;
+
+        for name in &names {
+            let name = name.clone();
+            let name_score = fuzzy_score(query, &name);
+            let mut fields = Vec::new();
+
+            if let Some(passphrase) = passphrase {
+                let metadata = match index
+                    .as_ref()
+                    .and_then(|index| indexed_fields(self, index, &name))
+                {
+                    Some(metadata) => metadata.to_vec(),
+                    None => match self.get_credential(&name, passphrase) {
+                        Ok(credential) => searchable_fields(&credential),
+                        Err(err) => {
+                            unreadable.push(err);
+                            Vec::new()
+                        }
+                    },
+                };
+
+                fields = metadata
+                    .into_iter()
+                    .filter(|(key, value)| {
+                        key.to_lowercase().contains(&needle)
+                            || value.to_lowercase().contains(&needle)
+                    })
+                    .collect();
+            }
+
+            if name_score.is_none() && fields.is_empty() {
+                continue;
+            }
+
+            matches.push(SearchMatch {
+                score: name_score.unwrap_or_default() + fields.len() * query.chars().count(),
+                name_matched: name_score.is_some(),
+                name,
+                fields,
+            });
+        }
+
+        // A credential that cannot be decrypted is still matched by name. Only when none of
+        // them can be read, as with a wrong passphrase, is the search reported as failed.
+        if !unreadable.is_empty() && unreadable.len() == names.len() {
+            return Err(unreadable.remove(0));
+        }
+
+        matches.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.name.cmp(&b.name)));
+
+        Ok(matches)
+    }
+}
+
+pub fn search(query: &str, passphrase: Option<&str>) -> Result<Vec<SearchMatch>> {
+    Store::default().search(query, passphrase)
+}
@@ -0,0 +1,321 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use git2::{ObjectType, Oid};
+
+use super::{Credential, Error, ErrorKind, Result};
+use crate::credential::{escape, split_field, unescape};
+use crate::journal::write_atomic;
+use crate::otp::{OTPAUTH_FIELD, OTPAUTH_SCHEME};
+use crate::pgp::{decrypt_data, encrypt_data};
+use crate::store::Store;
+
+/// Fields that hold secrets rather than metadata, and are never written to the index.
+const SECRET_FIELDS: &[&str] = &["password", "passphrase", "secret", "pin", "token", "cvv"];
+
+/// Number of appended blocks after which loading the index rewrites it as a single block.
+const COMPACT_AFTER_BLOCKS: usize = 64;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct IndexEntry {
+    /// Id of the encrypted credential blob the fields were read from.
+    oid: Option<String>,
+    fields: Vec<(String, String)>,
+}
+
+pub(crate) type SearchIndex = BTreeMap<String, IndexEntry>;
+
+fn get_index_path(store: &Store) -> Result<PathBuf> {
+    Ok(store.config_path()?.join("index"))
+}
+
+fn blob_id(store: &Store, name: &str) -> Option<String> {
+    Oid::hash_file(ObjectType::Blob, store.repo_path().join(name))
+        .ok()
+        .map(|oid| oid.to_string())
+}
+
+fn is_searchable(key: &str, value: &str) -> bool {
+    let key = key.to_lowercase();
+
+    key != OTPAUTH_FIELD
+        && !value.starts_with(OTPAUTH_SCHEME)
+        && !SECRET_FIELDS.contains(&key.as_str())
+}
+
+/// Returns the fields of `credential` that can be searched, leaving out secrets such as
+/// otpauth URIs.
+pub(crate) fn searchable_fields(credential: &Credential) -> Vec<(String, String)> {
+    credential
+        .fields
+        .iter()
+        .filter(|(key, value)| is_searchable(key, value))
+        .cloned()
+        .collect()
+}
+
+fn entry_record(name: &str, entry: &IndexEntry) -> String {
+    let mut record = format!(
+        "entry\t{}\t{}",
+        escape(name, true),
+        entry.oid.as_deref().unwrap_or_default()
+    );
+
+    entry.fields.iter().for_each(|(key, value)| {
+        record.push_str(&format!("\t{}={}", escape(key, true), escape(value, false)));
+    });
+
+    record
+}
+
+fn put_record(store: &Store, name: &str, credential: &Credential) -> String {
+    entry_record(
+        name,
+        &IndexEntry {
+            oid: blob_id(store, name),
+            fields: searchable_fields(credential),
+        },
+    )
+}
+
+fn is_within(path: &str, name: &str) -> bool {
+    path == name
+        || path
+            .strip_prefix(name)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+fn apply_record(index: &mut SearchIndex, record: &str) {
+    let mut parts = record.split('\t');
+
+    match (parts.next(), parts.next().map(unescape)) {
+        (Some("put"), Some(name)) => {
+            index.insert(
+                name,
+                IndexEntry {
+                    oid: None,
+                    fields: parts.filter_map(split_field).collect(),
+                },
+            );
+        }
+        (Some("entry"), Some(name)) => {
+            let oid = parts.next().filter(|oid| !oid.is_empty());
+
+            index.insert(
+                name,
+                IndexEntry {
+                    oid: oid.map(|oid| oid.to_owned()),
+                    fields: parts.filter_map(split_field).collect(),
+                },
+            );
+        }
+        (Some("remove"), Some(name)) => {
+            index.retain(|path, _| !is_within(path, &name));
+        }
+        (Some(operation @ ("move" | "copy")), Some(from)) => {
+            let Some(to) = parts.next().map(unescape) else {
+                return;
+            };
+
+            let entries: Vec<String> = index
+                .keys()
+                .filter(|path| is_within(path, &from))
+                .cloned()
+                .collect();
+
+            entries.into_iter().for_each(|path| {
+                let fields = match operation {
+                    "move" => index.remove(&path),
+                    _ => index.get(&path).cloned(),
+                };
+
+                if let Some(fields) = fields {
+                    index.insert(format!("{}{}", to, &path[from.len()..]), fields);
+                }
+            });
+        }
+        _ => {}
+    }
+}
+
+fn encode_block(store: &Store, records: &[String]) -> Result<Vec<u8>> {
+    let block = encrypt_data(records.join("\n").as_bytes(), store.rsa_pub_key()?)?;
+    let mut data = (block.len() as u32).to_be_bytes().to_vec();
+    data.extend_from_slice(&block);
+
+    Ok(data)
+}
+
+fn append_records(store: &Store, records: &[String]) -> Result<()> {
+    let data = encode_block(store, records)?;
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(get_index_path(store)?)
+        .and_then(|mut file| file.write_all(&data))
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::PermissionDenied => Error::new(
+                ErrorKind::PermissionDenied,
+                "You dont have permission to update the search index",
+            )
+            .with_source(err),
+            _ => Error::new(
+                ErrorKind::Io,
+                format!("failed to update the search index. {}", err),
+            )
+            .with_source(err),
+        })
+}
+
+// Index updates are best effort: a credential change has already been
+// committed by the time these run, and `rebuild_index` repairs any drift.
+pub(crate) fn index_put(store: &Store, name: &str, credential: &Credential) {
+    let _ = append_records(store, &[put_record(store, name, credential)]);
+}
+
+pub(crate) fn index_remove(store: &Store, name: &str) {
+    let _ = append_records(store, &[format!("remove\t{}", escape(name, true))]);
+}
+
+pub(crate) fn index_move(store: &Store, from: &str, to: &str) {
+    let _ = append_records(
+        store,
+        &[format!(
+            "move\t{}\t{}",
+            escape(from, true),
+            escape(to, true)
+        )],
+    );
+}
+
+pub(crate) fn index_copy(store: &Store, from: &str, to: &str) {
+    let _ = append_records(
+        store,
+        &[format!(
+            "copy\t{}\t{}",
+            escape(from, true),
+            escape(to, true)
+        )],
+    );
+}
+
+/// Returns the indexed fields of `name`, unless the credential changed since it was indexed.
+pub(crate) fn indexed_fields<'a>(
+    store: &Store,
+    index: &'a SearchIndex,
+    name: &str,
+) -> Option<&'a [(String, String)]> {
+    let entry = index.get(name)?;
+
+    (entry.oid.is_some() && entry.oid == blob_id(store, name)).then_some(entry.fields.as_slice())
+}
+
+/// Reads the index log and returns it with the number of blocks it is made of.
+fn read_index(store: &Store, passphrase: &str) -> Result<Option<(SearchIndex, usize)>> {
+    let data = match fs::read(get_index_path(store)?) {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => {
+            return Err(
+                Error::new(ErrorKind::BadConfig, "failed to read the search index")
+                    .with_source(err),
+            )
+        }
+    };
+
+    let mut blocks = Vec::new();
+    let mut rest = data.as_slice();
+
+    while !rest.is_empty() {
+        let invalid_index = || Error::new(ErrorKind::BadConfig, "Invalid search index data");
+
+        let (size, remaining) = rest.split_at_checked(4).ok_or_else(invalid_index)?;
+        let size = u32::from_be_bytes([size[0], size[1], size[2], size[3]]) as usize;
+        let (block, remaining) = remaining.split_at_checked(size).ok_or_else(invalid_index)?;
+
+        blocks.push(block.to_vec());
+        rest = remaining;
+    }
+
+    let block_count = blocks.len();
+    let mut index = SearchIndex::new();
+
+    for block in decrypt_data(blocks, passphrase, store.private_key()?)? {
+        String::from_utf8_lossy(&block)
+            .lines()
+            .for_each(|record| apply_record(&mut index, record));
+    }
+
+    Ok(Some((index, block_count)))
+}
+
+fn write_index(store: &Store, records: &[String]) -> Result<()> {
+    let data = encode_block(store, records)?;
+
+    write_atomic(&get_index_path(store)?, &data).map_err(|err| match err.kind() {
+        io::ErrorKind::PermissionDenied => Error::new(
+            ErrorKind::PermissionDenied,
+            "You dont have permission to update the search index",
+        )
+        .with_source(err),
+        _ => Error::new(
+            ErrorKind::Io,
+            format!("failed to write the search index. {}", err),
+        )
+        .with_source(err),
+    })
+}
+
+/// Loads the search index, compacting its log into a single block once it has grown past
+/// [`COMPACT_AFTER_BLOCKS`] appended updates.
+pub(crate) fn load_index(store: &Store, passphrase: &str) -> Result<Option<SearchIndex>> {
+    let Some((index, block_count)) = read_index(store, passphrase)? else {
+        return Ok(None);
+    };
+
+    if block_count <= COMPACT_AFTER_BLOCKS {
+        return Ok(Some(index));
+    }
+
+    // Updates are appended under the store lock, so compacting under it loses none of them.
+    // Compaction is skipped, without waiting, while another process holds the lock.
+    let Ok(_lock) = store.open().and_then(|repo| store.try_lock(&repo)) else {
+        return Ok(Some(index));
+    };
+    let Some((index, _)) = read_index(store, passphrase)? else {
+        return Ok(None);
+    };
+    let records: Vec<String> = index
+        .iter()
+        .map(|(name, entry)| entry_record(name, entry))
+        .collect();
+    let _ = write_index(store, &records);
+
+    Ok(Some(index))
+}
+
+impl Store {
+    pub fn rebuild_index(&self, passphrase: &str) -> Result<()> {
+        let _lock = self.lock(&self.open()?)?;
+        let records = self
+            .list_credentials(None)?
+            .iter()
+            .map(|name| {
+                Ok(put_record(
+                    self,
+                    name,
+                    &self.get_credential(name, passphrase)?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        write_index(self, &records)
+    }
+}
+
+pub fn rebuild_index(passphrase: &str) -> Result<()> {
+    Store::default().rebuild_index(passphrase)
+}
@@ -0,0 +1,122 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use git2::Repository;
+
+use super::{Error, ErrorKind, Result};
+use crate::config::{env_config_dir, env_private_key, get_config_dir, DEFAULT_LOCK_TIMEOUT};
+use crate::git::{open_repository, repo_path_from_env};
+use crate::lock::{lock_store, StoreLock};
+
+/// A password store rooted at an explicit repository and configuration directory.
+///
+/// Every operation of the crate is available as a method on `Store`, so a single process can
+/// work with several stores at once. The free functions at the crate root are wrappers over
+/// `Store::default()`, which is [`Store::from_env`] without pass compatibility. It resolves
+/// its locations in this order:
+///
+/// - repository: `RSPASS_STORE_DIR`, then `PASSWORD_STORE_DIR` if pass compatibility is
+///   enabled, then the home directory from [`set_home_dir`](crate::set_home_dir).
+/// - config directory: `RSPASS_CONFIG_DIR`, then the `rspass` folder inside the directory from
+///   [`set_config_dir`](crate::set_config_dir).
+/// - private key: `RSPASS_KEY`, then `PASSWORD_STORE_KEY` if pass compatibility is enabled and
+///   it names a file, then `keys.private_key` from the config files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Store {
+    repo_path: PathBuf,
+    config_path: Option<PathBuf>,
+    keys_path: Option<PathBuf>,
+    private_key_path: Option<PathBuf>,
+    lock_timeout: Duration,
+}
+
+impl Store {
+    /// Builds a store from the environment variables and the home and config directories.
+    ///
+    /// With `pass_compatibility`, `PASSWORD_STORE_DIR` and `PASSWORD_STORE_KEY` are honored
+    /// when the `RSPASS_` variables are unset.
+    pub fn from_env(pass_compatibility: bool) -> Self {
+        Store {
+            repo_path: repo_path_from_env(pass_compatibility),
+            config_path: env_config_dir()
+                .or_else(|| get_config_dir().map(|config_dir| config_dir.join("rspass"))),
+            keys_path: None,
+            private_key_path: env_private_key(pass_compatibility),
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+        }
+    }
+
+    pub fn new(repo_path: impl Into<PathBuf>, config_path: impl Into<PathBuf>) -> Self {
+        Store {
+            repo_path: repo_path.into(),
+            config_path: Some(config_path.into()),
+            keys_path: None,
+            private_key_path: None,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+        }
+    }
+
+    /// Reads the keys from `path` instead of the configuration directory.
+    pub fn with_keys_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.keys_path = Some(path.into());
+        self
+    }
+
+    /// Reads the private key from `path`, overriding `keys.private_key` from the config files.
+    pub fn with_private_key_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.private_key_path = Some(path.into());
+        self
+    }
+
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = timeout;
+        self
+    }
+
+    pub fn repo_path(&self) -> &Path {
+        &self.repo_path
+    }
+
+    pub fn config_path(&self) -> Result<&Path> {
+        self.config_path.as_deref().ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotInitialized,
+                "config_dir should already be defined",
+            )
+        })
+    }
+
+    pub fn keys_path(&self) -> Result<&Path> {
+        match &self.keys_path {
+            Some(keys_path) => Ok(keys_path),
+            None => self.config_path(),
+        }
+    }
+
+    pub fn private_key_path(&self) -> Option<&Path> {
+        self.private_key_path.as_deref()
+    }
+
+    pub fn lock_timeout(&self) -> Duration {
+        self.lock_timeout
+    }
+
+    pub(crate) fn open(&self) -> Result<Repository> {
+        open_repository(&self.repo_path)
+    }
+
+    pub(crate) fn lock(&self, repo: &Repository) -> Result<StoreLock> {
+        lock_store(repo, self.lock_timeout)
+    }
+
+    /// Takes the store lock only if no other process holds it, without waiting.
+    pub(crate) fn try_lock(&self, repo: &Repository) -> Result<StoreLock> {
+        lock_store(repo, Duration::ZERO)
+    }
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Store::from_env(false)
+    }
+}
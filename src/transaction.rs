@@ -0,0 +1,439 @@
+use super::{read_credential, Credential, CredentialName, Error, ErrorKind, Result};
+use crate::attachment::{attachment_path, attachments_path};
+use crate::journal::Journal;
+use crate::pgp::{encrypt, encrypt_data};
+use crate::search_index::{index_copy, index_move, index_put, index_remove};
+use crate::store::Store;
+use crate::transfer::{collect_files, transfer, TransferMode};
+
+enum Operation {
+    Insert {
+        name: CredentialName,
+        credential: Credential,
+    },
+    Replace {
+        name: CredentialName,
+        credential: Credential,
+    },
+    Edit {
+        name: CredentialName,
+        gpg_password: String,
+        password: Option<String>,
+        metadata: Option<Vec<(String, Option<String>)>>,
+        notes: Option<String>,
+    },
+    Transfer {
+        target: CredentialName,
+        destination: CredentialName,
+        force: bool,
+        mode: TransferMode,
+    },
+    Remove {
+        name: CredentialName,
+        recursive: bool,
+    },
+    Attach {
+        name: CredentialName,
+        attachment: CredentialName,
+        data: Vec<u8>,
+    },
+}
+
+enum IndexUpdate {
+    Put(CredentialName, Credential),
+    Remove(CredentialName),
+    Move(CredentialName, CredentialName),
+    Copy(CredentialName, CredentialName),
+}
+
+/// Stages credential mutations and applies them to the store with a single commit.
+///
+/// Nothing touches the store until [`Transaction::commit`] or [`Transaction::commit_to`] is
+/// called. If any operation fails while being applied, every file written so far is restored
+/// and no commit is made.
+#[derive(Default)]
+pub struct Transaction {
+    operations: Vec<Operation>,
+}
+
+pub(crate) fn apply_edit(
+    credential: &mut Credential,
+    password: Option<&str>,
+    metadata: Option<Vec<(String, Option<String>)>>,
+    notes: Option<&str>,
+) {
+    if let Some(pass) = password {
+        credential.password = pass.to_owned();
+    }
+
+    if let Some(metadata) = metadata {
+        metadata.into_iter().for_each(|item| {
+            let (key, value) = item;
+
+            match value {
+                Some(inner_value) => {
+                    credential.set(key, inner_value);
+                }
+                None => {
+                    credential.remove(key.as_str());
+                }
+            };
+        });
+    }
+
+    if let Some(notes) = notes {
+        credential.notes = notes.to_owned();
+    }
+}
+
+fn write_encrypted(
+    store: &Store,
+    journal: &mut Journal,
+    name: &CredentialName,
+    credential: &Credential,
+    create: bool,
+) -> Result<()> {
+    let data = encrypt(credential.serialize(), store.rsa_pub_key()?)?;
+
+    if create {
+        journal.create(name.as_str(), &data)
+    } else if name.path_in(journal.root()).is_file() {
+        journal.write(name.as_str(), &data)
+    } else {
+        Err(Error::new(
+            ErrorKind::NotFound,
+            format!("no credential found for {:?}", name.as_str()),
+        ))
+    }
+}
+
+fn remove_files(journal: &mut Journal, name: &CredentialName, recursive: bool) -> Result<()> {
+    let repo_path = journal.root().to_path_buf();
+    let attachments = attachments_path(name.as_str());
+
+    if name.path_in(&repo_path).is_dir() && !recursive {
+        return Err(Error::new(
+            ErrorKind::RemovalError,
+            format!(
+                "{:?} is a folder, remove it recursively instead",
+                name.as_str()
+            ),
+        ));
+    }
+
+    let mut removals = collect_files(&repo_path, name.as_str())?;
+
+    if repo_path.join(&attachments).exists() {
+        removals.extend(collect_files(&repo_path, &attachments)?);
+    }
+
+    for path in &removals {
+        journal.remove(path)?;
+    }
+
+    Ok(())
+}
+
+fn transfer_files(
+    journal: &mut Journal,
+    target: &CredentialName,
+    destination: &CredentialName,
+    force: bool,
+    mode: TransferMode,
+) -> Result<()> {
+    transfer(journal, target.as_str(), destination.as_str(), force, mode)?;
+
+    let target_attachments = attachments_path(target.as_str());
+
+    if journal.root().join(&target_attachments).exists() {
+        transfer(
+            journal,
+            &target_attachments,
+            &attachments_path(destination.as_str()),
+            force,
+            mode,
+        )?;
+    }
+
+    Ok(())
+}
+
+impl Operation {
+    fn apply(self, store: &Store, journal: &mut Journal) -> Result<Option<IndexUpdate>> {
+        match self {
+            Operation::Insert { name, credential } => {
+                write_encrypted(store, journal, &name, &credential, true)?;
+                Ok(Some(IndexUpdate::Put(name, credential)))
+            }
+            Operation::Replace { name, credential } => {
+                write_encrypted(store, journal, &name, &credential, false)?;
+                Ok(Some(IndexUpdate::Put(name, credential)))
+            }
+            Operation::Edit {
+                name,
+                gpg_password,
+                password,
+                metadata,
+                notes,
+            } => {
+                let mut credential =
+                    read_credential(store, &name.path_in(journal.root()), &gpg_password)?;
+
+                apply_edit(
+                    &mut credential,
+                    password.as_deref(),
+                    metadata,
+                    notes.as_deref(),
+                );
+                write_encrypted(store, journal, &name, &credential, false)?;
+
+                Ok(Some(IndexUpdate::Put(name, credential)))
+            }
+            Operation::Transfer {
+                target,
+                destination,
+                force,
+                mode,
+            } => {
+                transfer_files(journal, &target, &destination, force, mode)?;
+
+                Ok(Some(match mode {
+                    TransferMode::Move => IndexUpdate::Move(target, destination),
+                    TransferMode::Copy => IndexUpdate::Copy(target, destination),
+                }))
+            }
+            Operation::Remove { name, recursive } => {
+                remove_files(journal, &name, recursive)?;
+                Ok(Some(IndexUpdate::Remove(name)))
+            }
+            Operation::Attach {
+                name,
+                attachment,
+                data,
+            } => {
+                let data = encrypt_data(&data, store.rsa_pub_key()?)?;
+
+                journal.write(&attachment_path(&name, &attachment), &data)?;
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl IndexUpdate {
+    fn apply(self, store: &Store) {
+        match self {
+            IndexUpdate::Put(name, credential) => index_put(store, name.as_str(), &credential),
+            IndexUpdate::Remove(name) => index_remove(store, name.as_str()),
+            IndexUpdate::Move(from, to) => index_move(store, from.as_str(), to.as_str()),
+            IndexUpdate::Copy(from, to) => index_copy(store, from.as_str(), to.as_str()),
+        }
+    }
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Transaction::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.operations.len()
+    }
+
+    pub fn insert(&mut self, name: &str, credential: &Credential) -> Result<&mut Self> {
+        self.operations.push(Operation::Insert {
+            name: CredentialName::new(name)?,
+            credential: credential.clone(),
+        });
+
+        Ok(self)
+    }
+
+    pub fn replace(&mut self, name: &str, credential: &Credential) -> Result<&mut Self> {
+        self.operations.push(Operation::Replace {
+            name: CredentialName::new(name)?,
+            credential: credential.clone(),
+        });
+
+        Ok(self)
+    }
+
+    pub fn edit(
+        &mut self,
+        name: &str,
+        gpg_password: &str,
+        password: Option<&str>,
+        metadata: Option<Vec<(String, Option<String>)>>,
+        notes: Option<&str>,
+    ) -> Result<&mut Self> {
+        self.operations.push(Operation::Edit {
+            name: CredentialName::new(name)?,
+            gpg_password: gpg_password.to_owned(),
+            password: password.map(|pass| pass.to_owned()),
+            metadata,
+            notes: notes.map(|notes| notes.to_owned()),
+        });
+
+        Ok(self)
+    }
+
+    pub fn move_credential(
+        &mut self,
+        target: &str,
+        destination: &str,
+        force: bool,
+    ) -> Result<&mut Self> {
+        self.push_transfer(target, destination, force, TransferMode::Move)
+    }
+
+    pub fn copy_credential(
+        &mut self,
+        target: &str,
+        destination: &str,
+        force: bool,
+    ) -> Result<&mut Self> {
+        self.push_transfer(target, destination, force, TransferMode::Copy)
+    }
+
+    pub fn remove(&mut self, name: &str, recursive: bool) -> Result<&mut Self> {
+        self.operations.push(Operation::Remove {
+            name: CredentialName::new(name)?,
+            recursive,
+        });
+
+        Ok(self)
+    }
+
+    /// Writes an attachment, replacing any existing one. The credential is not checked, so this
+    /// must be staged after the operation that creates it.
+    pub(crate) fn attach(
+        &mut self,
+        name: &str,
+        attachment: &str,
+        data: Vec<u8>,
+    ) -> Result<&mut Self> {
+        self.operations.push(Operation::Attach {
+            name: CredentialName::new(name)?,
+            attachment: CredentialName::segment(attachment)?,
+            data,
+        });
+
+        Ok(self)
+    }
+
+    fn push_transfer(
+        &mut self,
+        target: &str,
+        destination: &str,
+        force: bool,
+        mode: TransferMode,
+    ) -> Result<&mut Self> {
+        self.operations.push(Operation::Transfer {
+            target: CredentialName::new(target)?,
+            destination: CredentialName::new(destination)?,
+            force,
+            mode,
+        });
+
+        Ok(self)
+    }
+
+    pub fn commit(self, message: &str) -> Result<()> {
+        self.commit_to(&Store::default(), message)
+    }
+
+    pub fn commit_to(self, store: &Store, message: &str) -> Result<()> {
+        if self.operations.is_empty() {
+            return Ok(());
+        }
+
+        let repository = store.open()?;
+        let _lock = store.lock(&repository)?;
+        let mut journal = Journal::new(store.repo_path());
+        let mut updates = Vec::with_capacity(self.operations.len());
+
+        for operation in self.operations {
+            match operation.apply(store, &mut journal) {
+                Ok(update) => updates.extend(update),
+                Err(err) => return Err(journal.rollback_after(err)),
+            }
+        }
+
+        journal.commit_all(&repository, &store.signature()?, message)?;
+
+        updates.into_iter().for_each(|update| update.apply(store));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use rsa::pkcs1::{EncodeRsaPublicKey, LineEnding};
+    use rsa::{RsaPrivateKey, RsaPublicKey};
+
+    fn test_store(name: &str) -> Store {
+        let dir = std::env::temp_dir().join(format!(
+            "rspass-transaction-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        let store = Store::new(dir.join("repo"), dir.join("config"));
+        store.initialize_repository().unwrap();
+
+        let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+        let pub_key = RsaPublicKey::from(&private_key)
+            .to_pkcs1_pem(LineEnding::LF)
+            .unwrap();
+        fs::create_dir_all(store.config_path().unwrap()).unwrap();
+        fs::write(store.config_path().unwrap().join("rspass.pem"), pub_key).unwrap();
+
+        store
+    }
+
+    fn head(store: &Store) -> git2::Oid {
+        store.open().unwrap().head().unwrap().target().unwrap()
+    }
+
+    #[test]
+    fn rolls_back_the_working_tree_when_a_step_fails() {
+        let store = test_store("rollback");
+        let mut transaction = Transaction::new();
+        transaction
+            .insert("web/mail", &Credential::new("old"))
+            .unwrap();
+        transaction.commit_to(&store, "insert").unwrap();
+
+        let committed = head(&store);
+        let mail = store.repo_path().join("web/mail");
+        let previous = fs::read(&mail).unwrap();
+
+        let mut transaction = Transaction::new();
+        transaction
+            .replace("web/mail", &Credential::new("new"))
+            .unwrap()
+            .insert("web/chat", &Credential::new("chat"))
+            .unwrap()
+            .insert("bank/card", &Credential::new("card"))
+            .unwrap()
+            .insert("web/mail", &Credential::new("duplicate"))
+            .unwrap();
+        let err = transaction.commit_to(&store, "batch").unwrap_err();
+
+        assert_eq!(err.kind, ErrorKind::AlreadyExists);
+        assert_eq!(fs::read(&mail).unwrap(), previous);
+        assert!(!store.repo_path().join("web/chat").exists());
+        assert!(!store.repo_path().join("bank").exists());
+        assert_eq!(head(&store), committed);
+        assert!(store.open().unwrap().statuses(None).unwrap().is_empty());
+    }
+}
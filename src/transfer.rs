@@ -0,0 +1,131 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::{Error, ErrorKind, Result};
+use crate::journal::Journal;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TransferMode {
+    Move,
+    Copy,
+}
+
+fn map_io_error(err: io::Error, action: &str) -> Error {
+    match err.kind() {
+        io::ErrorKind::NotFound => {
+            Error::new(ErrorKind::NotFound, "credential not found").with_source(err)
+        }
+        io::ErrorKind::PermissionDenied => Error::new(
+            ErrorKind::PermissionDenied,
+            format!("You dont have permission to {} this credential", action),
+        )
+        .with_source(err),
+        _ => Error::new(
+            ErrorKind::Io,
+            format!("failed to {} credential. {}", action, err),
+        )
+        .with_source(err),
+    }
+}
+
+fn collect_folder(repo_path: &Path, name: &str, files: &mut Vec<String>) -> Result<()> {
+    let entries = fs::read_dir(repo_path.join(name)).map_err(|err| map_io_error(err, "read"))?;
+
+    for entry in entries.flatten() {
+        let path = format!("{}/{}", name, entry.file_name().to_string_lossy());
+
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => collect_folder(repo_path, &path, files)?,
+            Ok(_) => files.push(path),
+            Err(err) => return Err(map_io_error(err, "read")),
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn collect_files(repo_path: &Path, name: &str) -> Result<Vec<String>> {
+    let path = repo_path.join(name);
+    let mut files = Vec::new();
+
+    if path.is_dir() {
+        collect_folder(repo_path, name, &mut files)?;
+    } else if path.exists() {
+        files.push(name.to_owned());
+    } else {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("no credential found for {:?}", name),
+        ));
+    }
+
+    files.sort();
+
+    Ok(files)
+}
+
+pub(crate) fn remove_empty_parents(repo_path: &Path, name: &str) {
+    let path = repo_path.join(name);
+
+    for parent in path.ancestors().skip(1) {
+        if parent == repo_path || !parent.starts_with(repo_path) || fs::remove_dir(parent).is_err()
+        {
+            break;
+        }
+    }
+}
+
+pub(crate) fn transfer(
+    journal: &mut Journal,
+    target: &str,
+    destination: &str,
+    force: bool,
+    mode: TransferMode,
+) -> Result<Vec<(String, String)>> {
+    let action = match mode {
+        TransferMode::Move => "move",
+        TransferMode::Copy => "copy",
+    };
+
+    if target == destination || destination.starts_with(&format!("{}/", target)) {
+        return Err(Error::new(
+            ErrorKind::EditionError,
+            format!("cannot {} {:?} into {:?}", action, target, destination),
+        ));
+    }
+
+    let repo_path = journal.root().to_path_buf();
+    let files = collect_files(&repo_path, target)?;
+
+    let pairs: Vec<(String, String)> = files
+        .into_iter()
+        .map(|from| {
+            let to = format!("{}{}", destination, &from[target.len()..]);
+            (from, to)
+        })
+        .collect();
+
+    for (_, to) in &pairs {
+        let to_path = repo_path.join(to);
+
+        if to_path.is_dir() || (to_path.exists() && !force) {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("A credential already exists with the name {:?}", to),
+            ));
+        }
+    }
+
+    for (from, to) in &pairs {
+        let data = fs::read(repo_path.join(from)).map_err(|err| map_io_error(err, action))?;
+
+        journal.write(to, &data)?;
+
+        if mode == TransferMode::Move {
+            journal.remove(from)?;
+        }
+    }
+
+    Ok(pairs)
+}
@@ -0,0 +1,253 @@
+use std::path::Path;
+
+use git2::{Direction, ErrorCode, Repository, StatusOptions};
+
+use super::{Error, ErrorKind, Result};
+use crate::git::get_remote_names;
+use crate::keys::{check_key, ValidateKey};
+use crate::pgp::{
+    derive_keys, same_rsa_pub_key, validate_private_key, validate_pub_key, validate_rsa_pub_key,
+};
+use crate::store::Store;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+/// The check of [`Store::verify_store`] that reported an issue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    Keys,
+    Credential,
+    WorkingTree,
+    Untracked,
+    Remote,
+}
+
+#[derive(Debug, Clone)]
+pub struct Issue {
+    pub severity: Severity,
+    pub check: Check,
+    /// Key file, credential name, repository path or remote name the issue is about.
+    pub subject: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    pub credentials_checked: usize,
+    pub remotes_checked: usize,
+    pub issues: Vec<Issue>,
+}
+
+impl VerifyReport {
+    /// Returns the highest severity among the issues, or `None` if nothing was reported.
+    pub fn severity(&self) -> Option<Severity> {
+        self.issues.iter().map(|issue| issue.severity).max()
+    }
+
+    /// Whether no issue of [`Severity::Error`] was found.
+    pub fn is_healthy(&self) -> bool {
+        self.severity() < Some(Severity::Error)
+    }
+
+    fn push(
+        &mut self,
+        severity: Severity,
+        check: Check,
+        subject: Option<&str>,
+        message: impl Into<String>,
+    ) {
+        self.issues.push(Issue {
+            severity,
+            check,
+            subject: subject.map(|subject| subject.to_owned()),
+            message: message.into(),
+        });
+    }
+}
+
+fn status_error(err: git2::Error) -> Error {
+    Error::new(
+        ErrorKind::Git,
+        format!("failed to read the working tree status. {}", err.message()),
+    )
+    .with_source(err)
+}
+
+impl Store {
+    /// Checks that the store is intact and returns every problem found.
+    ///
+    /// The keys must be present, parse and match each other, every credential must decrypt
+    /// with `passphrase`, the working tree must be clean with no untracked files, and every
+    /// remote must be reachable. An `Err` is only returned when the checks themselves cannot
+    /// run, such as when the repository is missing.
+    pub fn verify_store(&self, passphrase: &str) -> Result<VerifyReport> {
+        let repo = self.open()?;
+        let mut report = VerifyReport::default();
+
+        if self.verify_keys(passphrase, &mut report)? {
+            self.verify_credentials(passphrase, &mut report)?;
+        }
+
+        verify_working_tree(&repo, &mut report)?;
+        verify_remotes(&repo, &mut report)?;
+
+        Ok(report)
+    }
+
+    /// Returns whether the private key could be unlocked, so that credentials can be checked.
+    fn verify_keys(&self, passphrase: &str, report: &mut VerifyReport) -> Result<bool> {
+        let (pub_key_path, private_key_path, rsa_pub_key_path) = self.key_files()?;
+        let mut valid = [false; 3];
+        let key_files: [(&Path, ValidateKey); 3] = [
+            (&pub_key_path, validate_pub_key),
+            (&private_key_path, validate_private_key),
+            (&rsa_pub_key_path, validate_rsa_pub_key),
+        ];
+
+        for (index, (path, validate)) in key_files.into_iter().enumerate() {
+            let subject = path.to_string_lossy();
+
+            match check_key(path, validate)? {
+                Some(true) => valid[index] = true,
+                Some(false) => report.push(
+                    Severity::Error,
+                    Check::Keys,
+                    Some(&subject),
+                    "key file cannot be parsed",
+                ),
+                None => report.push(
+                    Severity::Error,
+                    Check::Keys,
+                    Some(&subject),
+                    "key file is missing",
+                ),
+            }
+        }
+
+        let [_, private_key_valid, rsa_pub_key_valid] = valid;
+
+        if !private_key_valid {
+            return Ok(false);
+        }
+
+        let keys = match derive_keys(&self.private_key()?, passphrase) {
+            Ok(keys) => keys,
+            Err(err) => {
+                report.push(
+                    Severity::Error,
+                    Check::Keys,
+                    Some(&private_key_path.to_string_lossy()),
+                    format!("failed to unlock the private key. {}", err.message),
+                );
+                return Ok(false);
+            }
+        };
+
+        if rsa_pub_key_valid && !same_rsa_pub_key(&keys.rsa_pub_key, &self.rsa_pub_key()?)? {
+            report.push(
+                Severity::Error,
+                Check::Keys,
+                Some(&rsa_pub_key_path.to_string_lossy()),
+                "RSA public key was not derived from the private key",
+            );
+        }
+
+        Ok(true)
+    }
+
+    fn verify_credentials(&self, passphrase: &str, report: &mut VerifyReport) -> Result<()> {
+        for name in self.list_credentials(None)? {
+            report.credentials_checked += 1;
+
+            if let Err(err) = self.get_credential(&name, passphrase) {
+                report.push(Severity::Error, Check::Credential, Some(&name), err.message);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn verify_working_tree(repo: &Repository, report: &mut VerifyReport) -> Result<()> {
+    let mut options = StatusOptions::new();
+    options
+        .include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .include_ignored(false);
+
+    let statuses = repo.statuses(Some(&mut options)).map_err(status_error)?;
+
+    for entry in statuses.iter() {
+        let path = String::from_utf8_lossy(entry.path_bytes());
+        let status = entry.status();
+
+        if status.is_conflicted() {
+            report.push(
+                Severity::Error,
+                Check::WorkingTree,
+                Some(&path),
+                "file has unresolved conflicts",
+            );
+        } else if status.is_wt_new() {
+            report.push(
+                Severity::Warning,
+                Check::Untracked,
+                Some(&path),
+                "file is not tracked by git",
+            );
+        } else {
+            report.push(
+                Severity::Warning,
+                Check::WorkingTree,
+                Some(&path),
+                "file has uncommitted changes",
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn verify_remotes(repo: &Repository, report: &mut VerifyReport) -> Result<()> {
+    let names = get_remote_names(repo)?;
+
+    if names.is_empty() {
+        report.push(Severity::Info, Check::Remote, None, "no remote configured");
+    }
+
+    for name in names {
+        report.remotes_checked += 1;
+
+        let result = repo
+            .find_remote(&name)
+            .and_then(|mut remote| remote.connect(Direction::Fetch).map(|_| remote))
+            .map(|mut remote| remote.disconnect());
+
+        match result {
+            Ok(_) => {}
+            Err(err) if err.code() == ErrorCode::Auth => report.push(
+                Severity::Info,
+                Check::Remote,
+                Some(&name),
+                "remote is reachable but requires credentials",
+            ),
+            Err(err) => report.push(
+                Severity::Warning,
+                Check::Remote,
+                Some(&name),
+                format!("remote is unreachable. {}", err.message()),
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+pub fn verify_store(passphrase: &str) -> Result<VerifyReport> {
+    Store::default().verify_store(passphrase)
+}